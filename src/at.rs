@@ -0,0 +1,545 @@
+//! Directory descriptors and the `*at` system calls.
+//!
+//! Entries are looked up, opened, renamed and unlinked relative to an open directory rather than
+//! by path, so that nobody can swap a directory on the way for a symlink between a check and what
+//! is done after it, and paths may be longer than `PATH_MAX`. The last component is never
+//! followed if it is a symlink.
+
+use std::ffi::{CStr, CString, OsStr, OsString};
+use std::fs::File;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::vec;
+
+/// An open directory.
+#[derive(Debug)]
+pub(crate) struct Dir {
+    file: File,
+}
+
+/// The status of an entry as `fstatat` saw it, without following symlinks.
+#[derive(Clone, Copy)]
+pub(crate) struct Stat(libc::stat);
+
+// The field types differ between platforms, so some of the casts are no-ops on some of them.
+#[allow(clippy::unnecessary_cast)]
+impl Stat {
+    pub fn is_file(&self) -> bool {
+        self.0.st_mode & libc::S_IFMT == libc::S_IFREG
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.0.st_mode & libc::S_IFMT == libc::S_IFDIR
+    }
+
+    pub fn is_symlink(&self) -> bool {
+        self.0.st_mode & libc::S_IFMT == libc::S_IFLNK
+    }
+
+    pub fn size(&self) -> u64 {
+        self.0.st_size as u64
+    }
+
+    /// The number of hard links.
+    pub fn links(&self) -> u64 {
+        self.0.st_nlink as u64
+    }
+
+    pub fn dev(&self) -> u64 {
+        self.0.st_dev as u64
+    }
+
+    /// The last modification time, in seconds since the Unix epoch.
+    pub fn mtime(&self) -> i64 {
+        self.0.st_mtime as i64
+    }
+
+    /// The device and inode, which identify the entry.
+    pub fn id(&self) -> (u64, u64) {
+        (self.0.st_dev as u64, self.0.st_ino as u64)
+    }
+
+    /// Whether the open `file` is the entry this status was taken of.
+    pub fn is_same(&self, file: &File) -> io::Result<bool> {
+        let metadata = file.metadata()?;
+        Ok(metadata.dev() == self.0.st_dev as u64 && metadata.ino() == self.0.st_ino as u64)
+    }
+}
+
+fn cstr(name: &OsStr) -> io::Result<CString> {
+    CString::new(name.as_bytes()).map_err(|_| io::Error::from_raw_os_error(libc::EINVAL))
+}
+
+#[cfg(target_os = "linux")]
+fn rename_noreplace(fd: RawFd, from: &CStr, to: &CStr) -> io::Result<()> {
+    cvt(unsafe {
+        libc::renameat2(fd, from.as_ptr(), fd, to.as_ptr(), libc::RENAME_NOREPLACE)
+    })?;
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn rename_noreplace(_fd: RawFd, _from: &CStr, _to: &CStr) -> io::Result<()> {
+    Err(io::Error::from_raw_os_error(libc::ENOSYS))
+}
+
+fn cvt(ret: libc::c_int) -> io::Result<libc::c_int> {
+    if ret == -1 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(ret)
+    }
+}
+
+impl Dir {
+    /// Opens the directory `path`. Symlinks on the way are followed, since they are part of what
+    /// was given. A path longer than `PATH_MAX` is opened one component at a time.
+    pub fn open(path: &Path) -> io::Result<Dir> {
+        let flags = libc::O_RDONLY | libc::O_DIRECTORY | libc::O_CLOEXEC;
+        let c_path = cstr(path.as_os_str())?;
+        match cvt(unsafe { libc::open(c_path.as_ptr(), flags) }) {
+            Ok(fd) => Ok(Dir::from_fd(fd)),
+            Err(ref e) if e.raw_os_error() == Some(libc::ENAMETOOLONG) => {
+                let mut dir = Dir::open(Path::new(if path.is_absolute() { "/" } else { "." }))?;
+                for component in path.components() {
+                    let name = match component {
+                        Component::Normal(name) => name,
+                        Component::ParentDir => OsStr::new(".."),
+                        _ => continue,
+                    };
+                    dir = dir.open_at(name, flags)?;
+                }
+                Ok(dir)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Opens the directory that `path` is in, and returns it with the name of `path` in it. Fails
+    /// with `EINVAL` for paths without a name, like `/` or `..`.
+    pub fn parent(path: &Path) -> io::Result<(Dir, OsString)> {
+        let name = path.file_name().ok_or_else(|| io::Error::from_raw_os_error(libc::EINVAL))?;
+        let dir = match path.parent() {
+            Some(parent) if parent != Path::new("") => Dir::open(parent)?,
+            _ => Dir::open(Path::new("."))?,
+        };
+        Ok((dir, name.to_os_string()))
+    }
+
+    /// Opens the directory that `path`, relative to this directory if it is not absolute, is
+    /// in, and returns it with the name of `path` in it. Symlinks on the way are followed. A path
+    /// without a name, like `..`, has to be a directory, which is looked up in its parent.
+    fn parent_of(&self, path: &Path) -> io::Result<(Dir, OsString)> {
+        let flags = libc::O_RDONLY | libc::O_DIRECTORY | libc::O_CLOEXEC;
+        let name = match path.file_name() {
+            Some(name) => name,
+            None => {
+                let dir = self.open_at(path.as_os_str(), flags)?;
+                let parent = dir.open_at(OsStr::new(".."), flags)?;
+                let name = parent.name_of(&dir)?;
+                return Ok((parent, name));
+            }
+        };
+        let parent = match path.parent() {
+            Some(parent) if parent != Path::new("") => parent,
+            _ => Path::new("."),
+        };
+        Ok((self.open_at(parent.as_os_str(), flags)?, name.to_os_string()))
+    }
+
+    /// The name of the subdirectory `dir` in this directory. Fails with `ENOENT` if it is not
+    /// in it, which is the case for `/`.
+    fn name_of(&self, dir: &Dir) -> io::Result<OsString> {
+        let metadata = dir.file.metadata()?;
+        let id = (metadata.dev(), metadata.ino());
+        for name in self.names()? {
+            if self.stat(&name).is_ok_and(|stat| stat.id() == id) {
+                return Ok(name);
+            }
+        }
+        Err(io::Error::from_raw_os_error(libc::ENOENT))
+    }
+
+    fn from_fd(fd: RawFd) -> Dir {
+        Dir { file: unsafe { File::from_raw_fd(fd) } }
+    }
+
+    fn open_at(&self, name: &OsStr, flags: libc::c_int) -> io::Result<Dir> {
+        let name = cstr(name)?;
+        let fd = cvt(unsafe { libc::openat(self.file.as_raw_fd(), name.as_ptr(), flags) })?;
+        Ok(Dir::from_fd(fd))
+    }
+
+    /// Opens the subdirectory `name`. Fails if it is a symlink.
+    pub fn open_dir(&self, name: &OsStr) -> io::Result<Dir> {
+        self.open_at(name,
+                     libc::O_RDONLY | libc::O_DIRECTORY | libc::O_NOFOLLOW | libc::O_CLOEXEC)
+    }
+
+    /// Opens the regular file `name` for writing, and for reading too with `read`. Fails if it is
+    /// a symlink or not a regular file.
+    pub fn open_file(&self, name: &OsStr, read: bool) -> io::Result<File> {
+        let access = if read { libc::O_RDWR } else { libc::O_WRONLY };
+        // Non-blocking, so that opening a FIFO put in the file's place does not hang.
+        let flags = access | libc::O_NOFOLLOW | libc::O_NOCTTY | libc::O_NONBLOCK | libc::O_CLOEXEC;
+        let c_name = cstr(name)?;
+        let fd = cvt(unsafe { libc::openat(self.file.as_raw_fd(), c_name.as_ptr(), flags) })?;
+        let file = unsafe { File::from_raw_fd(fd) };
+
+        if !file.metadata()?.is_file() {
+            return Err(io::Error::from_raw_os_error(libc::EINVAL));
+        }
+        let flags = cvt(unsafe { libc::fcntl(fd, libc::F_GETFL) })?;
+        cvt(unsafe { libc::fcntl(fd, libc::F_SETFL, flags & !libc::O_NONBLOCK) })?;
+        Ok(file)
+    }
+
+    pub fn stat(&self, name: &OsStr) -> io::Result<Stat> {
+        let name = cstr(name)?;
+        let mut stat = unsafe { std::mem::zeroed() };
+        cvt(unsafe {
+            libc::fstatat(self.file.as_raw_fd(),
+                          name.as_ptr(),
+                          &mut stat,
+                          libc::AT_SYMLINK_NOFOLLOW)
+        })?;
+        Ok(Stat(stat))
+    }
+
+    /// The target of the symlink `name`.
+    pub fn read_link(&self, name: &OsStr) -> io::Result<PathBuf> {
+        let name = cstr(name)?;
+        let mut buf = vec![0u8; libc::PATH_MAX as usize];
+        let len = unsafe {
+            libc::readlinkat(self.file.as_raw_fd(),
+                             name.as_ptr(),
+                             buf.as_mut_ptr() as *mut libc::c_char,
+                             buf.len())
+        };
+        if len == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        buf.truncate(len as usize);
+        Ok(PathBuf::from(OsStr::from_bytes(&buf)))
+    }
+
+    pub fn exists(&self, name: &OsStr) -> bool {
+        self.stat(name).is_ok()
+    }
+
+    /// Renames `from` to `to`, failing with `EEXIST` if `to` exists rather than replacing it.
+    pub fn rename(&self, from: &OsStr, to: &OsStr) -> io::Result<()> {
+        let from = cstr(from)?;
+        let to = cstr(to)?;
+        let fd = self.file.as_raw_fd();
+        match rename_noreplace(fd, &from, &to) {
+            // Not supported by the kernel or the filesystem; another process could still take
+            // `to` between the check and the rename here.
+            Err(ref e) if e.raw_os_error() == Some(libc::EINVAL) ||
+                          e.raw_os_error() == Some(libc::ENOSYS) => {
+                if self.exists(OsStr::from_bytes(to.to_bytes())) {
+                    return Err(io::Error::from_raw_os_error(libc::EEXIST));
+                }
+                cvt(unsafe { libc::renameat(fd, from.as_ptr(), fd, to.as_ptr()) })?;
+                Ok(())
+            }
+            result => result,
+        }
+    }
+
+    /// Unlinks `name`, which must not be a directory.
+    pub fn remove_file(&self, name: &OsStr) -> io::Result<()> {
+        let name = cstr(name)?;
+        cvt(unsafe { libc::unlinkat(self.file.as_raw_fd(), name.as_ptr(), 0) })?;
+        Ok(())
+    }
+
+    /// Removes the empty directory `name`.
+    pub fn remove_dir(&self, name: &OsStr) -> io::Result<()> {
+        let name = cstr(name)?;
+        cvt(unsafe { libc::unlinkat(self.file.as_raw_fd(), name.as_ptr(), libc::AT_REMOVEDIR) })?;
+        Ok(())
+    }
+
+    /// Flushes the directory, and with it renames and unlinks in it, to disk.
+    pub fn sync(&self) -> io::Result<()> {
+        self.file.sync_all()
+    }
+
+    pub fn as_raw_fd(&self) -> RawFd {
+        self.file.as_raw_fd()
+    }
+
+    /// The names of the entries, without `.` and `..`.
+    pub fn names(&self) -> io::Result<Vec<OsString>> {
+        // `fdopendir` takes the descriptor over, so it gets a duplicate of its own.
+        let fd = cvt(unsafe { libc::fcntl(self.file.as_raw_fd(), libc::F_DUPFD_CLOEXEC, 0) })?;
+        let stream = unsafe { libc::fdopendir(fd) };
+        if stream.is_null() {
+            let e = io::Error::last_os_error();
+            unsafe { libc::close(fd) };
+            return Err(e);
+        }
+        // The duplicate shares the offset, which is at the end if the directory was read before.
+        unsafe { libc::rewinddir(stream) };
+
+        let mut names = Vec::new();
+        let result = loop {
+            unsafe { *errno() = 0 };
+            let entry = unsafe { libc::readdir(stream) };
+            if entry.is_null() {
+                let e = io::Error::last_os_error();
+                break if e.raw_os_error() == Some(0) { Ok(()) } else { Err(e) };
+            }
+            let name = unsafe { CStr::from_ptr((*entry).d_name.as_ptr()) }.to_bytes();
+            if name != b"." && name != b".." {
+                names.push(OsStr::from_bytes(name).to_os_string());
+            }
+        };
+        unsafe { libc::closedir(stream) };
+
+        result.map(|()| names)
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+unsafe fn errno() -> *mut libc::c_int {
+    libc::__errno_location()
+}
+
+#[cfg(any(target_os = "macos", target_os = "ios", target_os = "freebsd", target_os = "dragonfly"))]
+unsafe fn errno() -> *mut libc::c_int {
+    libc::__error()
+}
+
+#[cfg(any(target_os = "openbsd", target_os = "netbsd"))]
+unsafe fn errno() -> *mut libc::c_int {
+    libc::__errno()
+}
+
+/// An entry of a directory.
+#[derive(Clone)]
+pub(crate) struct Entry {
+    /// The directory the entry is in.
+    pub dir: Arc<Dir>,
+    pub name: OsString,
+    /// The path it was reached by, for messages.
+    pub path: PathBuf,
+    pub stat: Stat,
+}
+
+impl Entry {
+    /// The entry that `path` names. Symlinks are followed on the way but not at the end.
+    pub fn of(path: &Path) -> io::Result<Entry> {
+        let (dir, name) = Dir::parent(path)?;
+        let stat = dir.stat(&name)?;
+        Ok(Entry {
+            dir: Arc::new(dir),
+            name,
+            path: path.to_path_buf(),
+            stat,
+        })
+    }
+
+    /// What this entry is once symlinks are followed: the entry itself if it is not a symlink.
+    /// The path is the one the links lead to, e.g. `dir/../target` for a link `dir/link` to
+    /// `../target`.
+    pub fn follow(&self) -> io::Result<Entry> {
+        let mut entry = self.clone();
+        // The same limit as Linux puts on a path lookup.
+        for _ in 0..40 {
+            if !entry.stat.is_symlink() {
+                return Ok(entry);
+            }
+            let target = entry.dir.read_link(&entry.name)?;
+            let (dir, name) = entry.dir.parent_of(&target)?;
+            let stat = dir.stat(&name)?;
+            entry = Entry {
+                dir: Arc::new(dir),
+                name,
+                path: entry.path.parent().unwrap_or_else(|| Path::new("")).join(target),
+                stat,
+            };
+        }
+        Err(io::Error::from_raw_os_error(libc::ELOOP))
+    }
+}
+
+/// What `Walk` comes across.
+pub(crate) enum Visit {
+    /// An entry, in pre-order: a directory comes before what is in it.
+    Entry(Entry),
+    /// Everything in this directory has been visited.
+    Leave(Entry),
+    /// The entry at this path could not be examined, or this directory could not be read. A
+    /// directory that could not be read is not left. Entries that disappear while the walk is
+    /// under way are skipped.
+    Error(PathBuf, io::Error),
+}
+
+/// Walks the tree below an entry through directory descriptors, without following symlinks.
+/// Only the directories on the way down to the current entry are kept open.
+pub(crate) struct Walk {
+    /// The entry to return next, before anything on the stack.
+    next: Option<Entry>,
+    /// A directory just returned, to be read on the next call.
+    enter: Option<Entry>,
+    stack: Vec<Frame>,
+}
+
+struct Frame {
+    dir: Arc<Dir>,
+    /// The entry of the directory itself, to be returned with `Visit::Leave`.
+    entry: Entry,
+    names: vec::IntoIter<OsString>,
+}
+
+impl Walk {
+    pub fn new(root: Entry) -> Walk {
+        Walk {
+            next: Some(root),
+            enter: None,
+            stack: Vec::new(),
+        }
+    }
+}
+
+impl Iterator for Walk {
+    type Item = Visit;
+
+    fn next(&mut self) -> Option<Visit> {
+        if let Some(entry) = self.next.take() {
+            if entry.stat.is_dir() {
+                self.enter = Some(entry.clone());
+            }
+            return Some(Visit::Entry(entry));
+        }
+
+        if let Some(entry) = self.enter.take() {
+            let opened = entry.dir
+                .open_dir(&entry.name)
+                .and_then(|dir| Ok((dir.names()?, dir)));
+            match opened {
+                Ok((names, dir)) => {
+                    self.stack.push(Frame {
+                        dir: Arc::new(dir),
+                        entry,
+                        names: names.into_iter(),
+                    })
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Some(Visit::Error(entry.path, e)),
+            }
+        }
+
+        let frame = self.stack.last_mut()?;
+        match frame.names.next() {
+            Some(name) => {
+                let path = frame.entry.path.join(&name);
+                match frame.dir.stat(&name) {
+                    Ok(stat) => {
+                        self.next = Some(Entry {
+                            dir: frame.dir.clone(),
+                            name,
+                            path,
+                            stat,
+                        });
+                        self.next()
+                    }
+                    Err(ref e) if e.kind() == io::ErrorKind::NotFound => self.next(),
+                    Err(e) => Some(Visit::Error(path, e)),
+                }
+            }
+            None => self.stack.pop().map(|frame| Visit::Leave(frame.entry)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::ffi::{CString, OsStr};
+    use std::fs::{self, File};
+    use std::io::Write;
+    use std::os::unix::fs::symlink;
+    use std::os::unix::io::{AsRawFd, FromRawFd};
+    use std::path::PathBuf;
+    use std::process;
+
+    use super::{Dir, Entry};
+    use {shred_file, Config, ShremError};
+
+    fn scratch(name: &str) -> PathBuf {
+        let root = env::temp_dir().join(format!("shrem-test-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir(&root).unwrap();
+        root
+    }
+
+    #[test]
+    fn last_component_is_not_followed() {
+        let root = scratch("symlink");
+        fs::write(root.join("target"), b"keep").unwrap();
+        symlink("target", root.join("link")).unwrap();
+
+        let entry = Entry::of(&root.join("link")).unwrap();
+        assert!(entry.stat.is_symlink());
+        assert!(entry.dir.open_file(OsStr::new("link"), false).is_err());
+        match shred_file(root.join("link"), &Config::default()) {
+            Err(ShremError::NotARegularFile(_)) => {}
+            result => panic!("{:?}", result),
+        }
+        assert_eq!(fs::read(root.join("target")).unwrap(), b"keep");
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn paths_longer_than_path_max() {
+        let root = scratch("long-path");
+        let name = "d".repeat(200);
+        let c_name = CString::new(name.clone()).unwrap();
+        let flags = libc::O_RDONLY | libc::O_DIRECTORY | libc::O_CLOEXEC;
+
+        let mut path = root.clone();
+        let mut dir = Dir::open(&root).unwrap();
+        for _ in 0..30 {
+            assert_eq!(unsafe { libc::mkdirat(dir.file.as_raw_fd(), c_name.as_ptr(), 0o700) }, 0);
+            dir = dir.open_at(OsStr::new(&name), flags).unwrap();
+            path.push(&name);
+        }
+        let fd = unsafe {
+            libc::openat(dir.file.as_raw_fd(),
+                         b"file\0".as_ptr() as *const libc::c_char,
+                         libc::O_WRONLY | libc::O_CREAT | libc::O_CLOEXEC,
+                         0o600)
+        };
+        assert!(fd >= 0);
+        unsafe { File::from_raw_fd(fd) }.write_all(b"data").unwrap();
+        path.push("file");
+        assert!(path.as_os_str().len() > libc::PATH_MAX as usize);
+
+        shred_file(&path, &Config::default()).unwrap();
+        assert!(dir.names().unwrap().is_empty());
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn rename_does_not_replace() {
+        let root = scratch("rename");
+        fs::write(root.join("a"), b"a").unwrap();
+        fs::write(root.join("b"), b"b").unwrap();
+
+        let dir = Dir::open(&root).unwrap();
+        let err = dir.rename(OsStr::new("a"), OsStr::new("b")).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::EEXIST));
+        assert_eq!(fs::read(root.join("b")).unwrap(), b"b");
+
+        dir.rename(OsStr::new("a"), OsStr::new("c")).unwrap();
+        assert_eq!(fs::read(root.join("c")).unwrap(), b"a");
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
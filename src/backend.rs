@@ -0,0 +1,611 @@
+//! Programs that can do the actual overwriting of a single file.
+
+use std::env;
+use std::ffi::OsStr;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader};
+use std::os::unix::io::AsRawFd;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use super::{is_interrupted, Config, ShremError};
+use at::Dir;
+use event::{self, Event};
+use native::Native;
+
+/// Something that overwrites a regular file. Renaming and unlinking it afterwards is left to the
+/// caller, unless the backend `removes` files itself.
+pub trait Backend {
+    /// Name used with `--backend` and `SHREM_BACKEND`.
+    fn name(&self) -> &'static str;
+
+    /// Whether this backend can be used on this system.
+    fn is_available(&self) -> bool;
+
+    fn overwrite(&self, target: &Target, config: &Config) -> Result<(), ShremError>;
+
+    /// How many passes `overwrite` writes with `config`, if that is known. Programs that pick
+    /// their own scheme say so in their documentation, which may not match the version installed.
+    fn passes(&self, _config: &Config) -> Option<usize> {
+        None
+    }
+
+    /// Whether `overwrite` also removes the file (unless `--no-remove` is given).
+    fn removes(&self) -> bool {
+        false
+    }
+}
+
+/// The file a backend is to overwrite.
+pub struct Target<'a> {
+    /// Path of the file, for messages.
+    pub path: &'a Path,
+    /// The file, opened without following symlinks and checked to be the one that was found.
+    pub file: &'a File,
+    /// The directory the file is in, and its name there.
+    pub(crate) dir: &'a Dir,
+    pub(crate) name: &'a OsStr,
+}
+
+/// Lets the process run by `cmd` open `file` as `/dev/fd/N`, and returns that path. Programs are
+/// given this rather than the file's path, so that they write to the file that was checked.
+fn pass_file(cmd: &mut Command, file: &File) -> PathBuf {
+    let fd = file.as_raw_fd();
+    unsafe {
+        cmd.pre_exec(move || {
+            // Only the child's copy of the descriptor loses close-on-exec.
+            if libc::fcntl(fd, libc::F_SETFD, 0) == -1 {
+                Err(io::Error::last_os_error())
+            } else {
+                Ok(())
+            }
+        });
+    }
+    PathBuf::from(format!("/dev/fd/{}", fd))
+}
+
+/// Runs `cmd` in `dir`, for programs that need the file's name.
+fn run_in(cmd: &mut Command, dir: &Dir) {
+    let fd = dir.as_raw_fd();
+    unsafe {
+        cmd.pre_exec(move || {
+            if libc::fchdir(fd) == -1 {
+                Err(io::Error::last_os_error())
+            } else {
+                Ok(())
+            }
+        });
+    }
+}
+
+/// Backend selected with `--backend`, by name. [`backend`](#method.backend) resolves it to an
+/// implementation.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BackendKind {
+    Native,
+    /// Whichever `shred` is installed, GNU coreutils or busybox.
+    Shred,
+    GnuShred,
+    BusyboxShred,
+    Srm,
+    Wipe,
+    Scrub,
+}
+
+pub static NAMES: &[&str] =
+    &["native", "shred", "gnu-shred", "busybox-shred", "srm", "wipe", "scrub"];
+
+impl BackendKind {
+    pub fn all() -> &'static [BackendKind] {
+        static ALL: &[BackendKind] = &[BackendKind::Native,
+                                       BackendKind::Shred,
+                                       BackendKind::GnuShred,
+                                       BackendKind::BusyboxShred,
+                                       BackendKind::Srm,
+                                       BackendKind::Wipe,
+                                       BackendKind::Scrub];
+        ALL
+    }
+
+    pub fn from_name(name: &str) -> Option<BackendKind> {
+        BackendKind::all().iter().cloned().find(|kind| kind.name() == name)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            BackendKind::Shred => "shred",
+            other => other.backend().name(),
+        }
+    }
+
+    /// Resolves the selection to an implementation. For `Shred`, GNU shred is preferred over the
+    /// busybox applet if both are installed.
+    pub fn backend(&self) -> &'static dyn Backend {
+        static NATIVE: Native = Native;
+        static GNU_SHRED: GnuShred = GnuShred;
+        static BUSYBOX_SHRED: BusyboxShred = BusyboxShred;
+        static SRM: Srm = Srm;
+        static WIPE: Wipe = Wipe;
+        static SCRUB: Scrub = Scrub;
+
+        match *self {
+            BackendKind::Native => &NATIVE,
+            BackendKind::Shred => {
+                if !GNU_SHRED.is_available() && BUSYBOX_SHRED.is_available() {
+                    &BUSYBOX_SHRED
+                } else {
+                    &GNU_SHRED
+                }
+            }
+            BackendKind::GnuShred => &GNU_SHRED,
+            BackendKind::BusyboxShred => &BUSYBOX_SHRED,
+            BackendKind::Srm => &SRM,
+            BackendKind::Wipe => &WIPE,
+            BackendKind::Scrub => &SCRUB,
+        }
+    }
+}
+
+/// Looks up an executable in `$PATH`.
+fn find_in_path(program: &str) -> Option<PathBuf> {
+    let paths = env::var_os("PATH")?;
+    env::split_paths(&paths).map(|dir| dir.join(program)).find(|p| p.is_file())
+}
+
+/// Whether `program` in `$PATH` is a symlink to busybox rather than the real tool.
+fn is_busybox_applet(program: &str) -> bool {
+    find_in_path(program)
+        .and_then(|p| fs::canonicalize(p).ok())
+        .is_some_and(|p| p.file_name().is_some_and(|name| name == "busybox"))
+}
+
+/// Fails if an overwrite method (which only the native engine and `scrub` understand) or
+/// verification or extent checks (which only the native engine can do) were requested.
+fn require_plain(config: &Config) -> Result<(), ShremError> {
+    if config.method.is_some() {
+        return Err(ShremError::Unsupported("--method requires the native or scrub backend"));
+    }
+    require_no_checks(config)
+}
+
+fn require_no_checks(config: &Config) -> Result<(), ShremError> {
+    if config.verify {
+        Err(ShremError::Unsupported("--verify requires the native backend"))
+    } else if config.check_extents {
+        Err(ShremError::Unsupported("--check-extents requires the native backend"))
+    } else {
+        Ok(())
+    }
+}
+
+/// Starts `cmd` with no signals blocked. shrem blocks the ones it handles in every thread, and a
+/// child would otherwise inherit that and ignore being told to stop.
+fn spawn(cmd: &mut Command) -> io::Result<Child> {
+    unsafe {
+        cmd.pre_exec(|| {
+            let mut set = std::mem::zeroed();
+            libc::sigemptyset(&mut set);
+            if libc::sigprocmask(libc::SIG_SETMASK, &set, std::ptr::null_mut()) == -1 {
+                Err(io::Error::last_os_error())
+            } else {
+                Ok(())
+            }
+        });
+    }
+    cmd.spawn()
+}
+
+fn run(mut cmd: Command) -> Result<(), ShremError> {
+    let mut child = spawn(&mut cmd)?;
+    let watch = Watch::start(&child);
+    let status = child.wait();
+    finish(watch, status)
+}
+
+/// Kills a backend process when `interrupt()` is called while it runs.
+struct Watch {
+    done: Arc<AtomicBool>,
+    thread: JoinHandle<()>,
+}
+
+impl Watch {
+    fn start(child: &Child) -> Watch {
+        let pid = child.id() as libc::pid_t;
+        let done = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let done = done.clone();
+            thread::spawn(move || {
+                while !done.load(Ordering::SeqCst) {
+                    if is_interrupted() {
+                        // The process has not been waited for yet, so the pid is still ours.
+                        unsafe { libc::kill(pid, libc::SIGTERM) };
+                        break;
+                    }
+                    thread::sleep(Duration::from_millis(50));
+                }
+            })
+        };
+        Watch { done, thread }
+    }
+}
+
+/// Stops `watch` and turns the exit status of the process into a result. A process that was
+/// running when `interrupt()` was called counts as interrupted even if it exited successfully, as
+/// some programs do when they are stopped, so that the file is not taken for destroyed.
+fn finish(watch: Watch, status: io::Result<ExitStatus>) -> Result<(), ShremError> {
+    watch.done.store(true, Ordering::SeqCst);
+    let _ = watch.thread.join();
+
+    let status = status?;
+    if is_interrupted() {
+        Err(ShremError::Interrupted)
+    } else if status.success() {
+        Ok(())
+    } else {
+        Err(ShremError::ExternalProcessError(status))
+    }
+}
+
+/// `shred` from GNU coreutils.
+pub struct GnuShred;
+
+impl Backend for GnuShred {
+    fn name(&self) -> &'static str {
+        "gnu-shred"
+    }
+
+    fn is_available(&self) -> bool {
+        find_in_path("shred").is_some() && !is_busybox_applet("shred")
+    }
+
+    fn passes(&self, config: &Config) -> Option<usize> {
+        Some(shred_passes(config))
+    }
+
+    fn overwrite(&self, target: &Target, config: &Config) -> Result<(), ShremError> {
+        require_plain(config)?;
+
+        let mut cmd = Command::new("shred");
+        if config.zero {
+            cmd.arg("-z");
+        }
+        if let Some(n) = config.iterations {
+            cmd.arg("-n").arg(n.to_string());
+        }
+
+        let verbose = config.verbose || config.observer.is_some();
+        if verbose {
+            cmd.arg("-v");
+        }
+        let file = pass_file(&mut cmd, target.file);
+        cmd.arg("--").arg(file);
+
+        if verbose {
+            run_verbose_shred(cmd, target, config)
+        } else {
+            run(cmd)
+        }
+    }
+}
+
+/// The passes of GNU and busybox `shred`: three random ones unless `-n` says otherwise, and a zero
+/// pass with `-z`.
+fn shred_passes(config: &Config) -> usize {
+    config.iterations.unwrap_or(3) + if config.zero { 1 } else { 0 }
+}
+
+/// Runs GNU `shred -v` and turns what it prints into events, so that its passes show up like
+/// those of the native engine. Lines that are not understood are passed on to stderr.
+fn run_verbose_shred(mut cmd: Command, target: &Target, config: &Config) -> Result<(), ShremError> {
+    let path = target.path;
+    let size = target.file.metadata()?.len();
+    let mut child = spawn(cmd.stderr(Stdio::piped()))?;
+    let stderr = child.stderr.take().unwrap();
+    let watch = Watch::start(&child);
+
+    // The pass being written and when it started.
+    let mut current: Option<(usize, Instant)> = None;
+    let pass_done = |current: &mut Option<(usize, Instant)>| {
+        if let Some((pass, start)) = current.take() {
+            event::emit(config,
+                        Event::PassDone { path, pass, bytes: size, duration: start.elapsed() });
+        }
+    };
+
+    // A read error ends the loop like EOF; the exit status tells what happened.
+    for line in BufReader::new(stderr).lines().map_while(Result::ok) {
+        let message = match line.strip_prefix("shred: ") {
+            Some(message) => message,
+            None => {
+                eprintln!("{}", line);
+                continue;
+            }
+        };
+
+        if let Some((pass, total, pattern, percent)) = parse_pass(message) {
+            if current.map(|(p, _)| p) != Some(pass) {
+                pass_done(&mut current);
+                event::emit(config, Event::PassStart { path, pass, total, pattern });
+                current = Some((pass, Instant::now()));
+            }
+            if let Some(percent) = percent {
+                event::emit(config, Event::Progress { path, pass, bytes: size * percent / 100 });
+            }
+        } else {
+            eprintln!("{}", line);
+        }
+    }
+
+    let status = child.wait();
+    finish(watch, status)?;
+    pass_done(&mut current);
+    Ok(())
+}
+
+/// Parses `NAME: pass 2/4 (random)...` as printed by `shred -v`, which is followed by
+/// `100MiB/1.0GiB 10%` on passes that take longer than a few seconds. Returns the pass, the pass
+/// count, the pattern and the percentage, if any.
+fn parse_pass(message: &str) -> Option<(usize, usize, &str, Option<u64>)> {
+    let rest = &message[message.rfind(": pass ")? + ": pass ".len()..];
+    let (passes, rest) = rest.split_once(" (")?;
+    let (pattern, progress) = rest.split_once(")...")?;
+    let (pass, total) = passes.split_once('/')?;
+
+    let percent = progress.trim_end()
+        .strip_suffix('%')
+        .and_then(|s| s.rsplit(' ').next())
+        .and_then(|s| s.parse().ok());
+
+    Some((pass.parse().ok()?, total.parse().ok()?, pattern, percent))
+}
+
+/// The `shred` applet of busybox. It has no verbose mode.
+pub struct BusyboxShred;
+
+impl Backend for BusyboxShred {
+    fn name(&self) -> &'static str {
+        "busybox-shred"
+    }
+
+    /// Busybox can be built without the applet, so it is looked for in `busybox --list`.
+    fn is_available(&self) -> bool {
+        static AVAILABLE: OnceLock<bool> = OnceLock::new();
+
+        *AVAILABLE.get_or_init(|| {
+            find_in_path("busybox").is_some() &&
+            Command::new("busybox")
+                .arg("--list")
+                .stdin(Stdio::null())
+                .stderr(Stdio::null())
+                .output()
+                .is_ok_and(|output| {
+                    output.status.success() &&
+                    output.stdout.split(|&b| b == b'\n').any(|applet| applet == b"shred")
+                })
+        })
+    }
+
+    fn passes(&self, config: &Config) -> Option<usize> {
+        Some(shred_passes(config))
+    }
+
+    fn overwrite(&self, target: &Target, config: &Config) -> Result<(), ShremError> {
+        require_plain(config)?;
+
+        let mut cmd = Command::new("busybox");
+        cmd.arg("shred");
+        if config.zero {
+            cmd.arg("-z");
+        }
+        if let Some(n) = config.iterations {
+            cmd.arg("-n").arg(n.to_string());
+        }
+        let file = pass_file(&mut cmd, target.file);
+        cmd.arg("--").arg(file);
+        run(cmd)
+    }
+}
+
+/// `srm` from the secure-delete package. Its pass count can only be lowered to one (`-ll`) or two
+/// (`-l`); anything else runs the default 38-pass scheme.
+///
+/// `srm` always removes the file, so it needs the name rather than the checked descriptor. It is
+/// run in the file's directory, which keeps the directories on the way from being swapped, but
+/// not the file itself.
+pub struct Srm;
+
+impl Backend for Srm {
+    fn name(&self) -> &'static str {
+        "srm"
+    }
+
+    fn is_available(&self) -> bool {
+        find_in_path("srm").is_some()
+    }
+
+    fn removes(&self) -> bool {
+        true
+    }
+
+    fn passes(&self, config: &Config) -> Option<usize> {
+        match config.iterations {
+            Some(n @ 1) | Some(n @ 2) => Some(n),
+            _ => None,
+        }
+    }
+
+    fn overwrite(&self, target: &Target, config: &Config) -> Result<(), ShremError> {
+        require_plain(config)?;
+
+        if config.no_remove {
+            return Err(ShremError::Unsupported("srm cannot overwrite without removing"));
+        }
+
+        let mut cmd = Command::new("srm");
+        match config.iterations {
+            Some(1) => {
+                cmd.arg("-ll");
+            }
+            Some(2) => {
+                cmd.arg("-l");
+            }
+            _ => {}
+        }
+        if config.zero {
+            cmd.arg("-z");
+        }
+        if config.verbose {
+            cmd.arg("-v");
+        }
+        run_in(&mut cmd, target.dir);
+        cmd.arg("--").arg(Path::new(".").join(target.name));
+        run(cmd)
+    }
+}
+
+/// `wipe`, run in quick mode so that the pass count can be set. It has no final zero pass.
+///
+/// `wipe` does not follow symlinks unless told to with `-D`, and `/dev/fd/N` is one; without it,
+/// it would skip the file and still succeed.
+pub struct Wipe;
+
+impl Backend for Wipe {
+    fn name(&self) -> &'static str {
+        "wipe"
+    }
+
+    fn is_available(&self) -> bool {
+        find_in_path("wipe").is_some()
+    }
+
+    fn passes(&self, config: &Config) -> Option<usize> {
+        config.iterations
+    }
+
+    fn overwrite(&self, target: &Target, config: &Config) -> Result<(), ShremError> {
+        require_plain(config)?;
+        run(wipe_command(target.file, config))
+    }
+}
+
+fn wipe_command(file: &File, config: &Config) -> Command {
+    let mut cmd = Command::new("wipe");
+    cmd.arg("-f").arg("-k").arg("-D");
+    if let Some(n) = config.iterations {
+        cmd.arg("-q").arg("-Q").arg(n.to_string());
+    }
+    if config.verbose {
+        cmd.arg("-i");
+    } else {
+        cmd.arg("-s");
+    }
+    let file = pass_file(&mut cmd, file);
+    cmd.arg("--").arg(file);
+    cmd
+}
+
+/// `scrub`. The number of passes is determined by its pattern, so `-n` selects between a single
+/// random pass and the default NNSA scheme. Methods are mapped to scrub's own patterns of the same
+/// name where it has one.
+pub struct Scrub;
+
+impl Backend for Scrub {
+    fn name(&self) -> &'static str {
+        "scrub"
+    }
+
+    fn is_available(&self) -> bool {
+        find_in_path("scrub").is_some()
+    }
+
+    fn passes(&self, config: &Config) -> Option<usize> {
+        match config.method {
+            None if config.iterations == Some(1) => Some(1),
+            _ => None,
+        }
+    }
+
+    fn overwrite(&self, target: &Target, config: &Config) -> Result<(), ShremError> {
+        require_no_checks(config)?;
+
+        let mut cmd = Command::new("scrub");
+        match config.method {
+            Some(method) => {
+                let pattern = match method.name {
+                    "dod" | "gutmann" | "schneier" => method.name,
+                    _ => return Err(ShremError::Unsupported("scrub has no such pattern")),
+                };
+                cmd.arg("-p").arg(pattern);
+            }
+            None => {
+                if config.iterations == Some(1) {
+                    cmd.arg("-p").arg("random");
+                }
+            }
+        }
+        let file = pass_file(&mut cmd, target.file);
+        cmd.arg("--").arg(file);
+        run(cmd)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::ffi::OsStr;
+    use std::fs::{self, File};
+    use std::os::unix::io::AsRawFd;
+    use std::process;
+
+    use super::{wipe_command, BackendKind};
+    use {Config, Shredder};
+
+    #[test]
+    fn wipe_command_line() {
+        let file = File::open("/dev/null").unwrap();
+        let fd = format!("/dev/fd/{}", file.as_raw_fd());
+
+        let config = Config {
+            iterations: Some(2),
+            ..Config::default()
+        };
+        let cmd = wipe_command(&file, &config);
+        assert_eq!(cmd.get_program(), "wipe");
+        assert_eq!(cmd.get_args().collect::<Vec<_>>(),
+                   ["-f", "-k", "-D", "-q", "-Q", "2", "-s", "--", &fd].iter()
+                       .map(OsStr::new)
+                       .collect::<Vec<_>>());
+
+        let config = Config {
+            verbose: true,
+            ..Config::default()
+        };
+        let cmd = wipe_command(&file, &config);
+        assert_eq!(cmd.get_args().collect::<Vec<_>>(),
+                   ["-f", "-k", "-D", "-i", "--", &fd].iter()
+                       .map(OsStr::new)
+                       .collect::<Vec<_>>());
+    }
+
+    #[test]
+    #[ignore = "needs wipe to be installed"]
+    fn wipe_overwrites_the_file() {
+        let path = env::temp_dir().join(format!("shrem-test-wipe-{}", process::id()));
+        let data = vec![0x5a; 64 * 1024];
+        fs::write(&path, &data).unwrap();
+        Shredder::new()
+            .backend(BackendKind::Wipe)
+            .passes(1)
+            .remove(false)
+            .shred(&path)
+            .unwrap();
+        let after = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_ne!(after, data);
+    }
+}
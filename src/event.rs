@@ -0,0 +1,652 @@
+//! Progress events, printed either as the human-readable `-v` lines or as JSON.
+
+use std::cell::RefCell;
+use std::fmt::{self, Write as FmtWrite};
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::{Config, HardLinks, OnCow, ShremError};
+use journal;
+use method;
+
+/// How events are printed, selected with `--output`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Nothing is printed. The default for library users.
+    None,
+    /// `shred`-style lines on stdout when `-v` is given, errors on stderr.
+    Text,
+    /// One JSON object per line on stdout, regardless of `-v`.
+    Json,
+}
+
+impl OutputFormat {
+    pub fn from_name(name: &str) -> Option<OutputFormat> {
+        match name {
+            "text" => Some(OutputFormat::Text),
+            "json" => Some(OutputFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+/// Something that happened while shredding. Paths are as given, or as found by the walk.
+#[derive(Debug)]
+pub enum Event<'a> {
+    /// A regular file is about to be overwritten.
+    Start {
+        path: &'a Path,
+        bytes: u64,
+    },
+    PassStart {
+        path: &'a Path,
+        pass: usize,
+        total: usize,
+        pattern: &'a str,
+    },
+    /// Part of a pass has been written: `bytes` from the start of the file. Emitted every few
+    /// megabytes by the native engine and whenever GNU shred reports progress; never printed.
+    Progress {
+        path: &'a Path,
+        pass: usize,
+        bytes: u64,
+    },
+    PassDone {
+        path: &'a Path,
+        pass: usize,
+        bytes: u64,
+        duration: Duration,
+    },
+    /// Overwriting may not destroy data on the filesystem of type `fstype` from `source` mounted
+    /// on `mount_point`, for the reason `advice` gives. Emitted once per mount, before the first
+    /// file on it.
+    FilesystemAdvisory {
+        mount_point: &'a Path,
+        fstype: &'a str,
+        source: &'a str,
+        advice: &'a str,
+    },
+    /// A regular file is on an overlay. If the layers could be looked at (`known`), the file is
+    /// stored in the `upper` one, or in a `lower` one, or in both if it was copied up.
+    Overlay {
+        path: &'a Path,
+        upper: Option<&'a Path>,
+        lower: Option<&'a Path>,
+        known: bool,
+    },
+    /// `--check-extents`: after pass `pass`, the data of a regular file was found in the blocks
+    /// it was in before, or `relocated` to others.
+    ExtentsChecked {
+        path: &'a Path,
+        pass: usize,
+        relocated: bool,
+    },
+    Verifying {
+        path: &'a Path,
+    },
+    Verified {
+        path: &'a Path,
+        duration: Duration,
+    },
+    /// The entry is about to be unlinked.
+    Removing {
+        path: &'a Path,
+    },
+    Renamed {
+        from: &'a Path,
+        to: &'a Path,
+    },
+    Removed {
+        path: &'a Path,
+    },
+    /// A regular file has `links` names in all, which share its data.
+    HardLinked {
+        path: &'a Path,
+        links: u64,
+    },
+    /// Overwriting a regular file will write new blocks and leave its data where it is: it is on
+    /// the copy-on-write `filesystem`, which is reported for the first file on it, or its extents
+    /// are `shared` with other files or snapshots.
+    CopyOnWrite {
+        path: &'a Path,
+        filesystem: Option<&'a str>,
+        shared: bool,
+    },
+    /// The entry was left alone: the user declined to remove it at the interactive prompt, or
+    /// it has other hard links and `--hardlinks=skip` is set.
+    Skipped {
+        path: &'a Path,
+    },
+    /// `--forensic-verify`: none of the `samples` taken of a regular file was found on `device`
+    /// after it was shredded.
+    ForensicVerified {
+        path: &'a Path,
+        device: &'a Path,
+        samples: usize,
+    },
+    /// A regular file has been shredded successfully.
+    Done {
+        path: &'a Path,
+        bytes: u64,
+        duration: Duration,
+    },
+    Error {
+        path: &'a Path,
+        error: &'a ShremError,
+    },
+    /// `--dry-run`: a regular file would be overwritten (and removed unless `--no-remove`).
+    WouldShred {
+        path: &'a Path,
+        bytes: u64,
+    },
+    /// `--dry-run`: a directory would be renamed and removed, or another file would be unlinked.
+    WouldRemove {
+        path: &'a Path,
+        dir: bool,
+    },
+    Summary {
+        duration: Duration,
+    },
+}
+
+/// Callback that receives every event, e.g. to drive a progress display.
+#[derive(Clone)]
+pub struct Observer(pub Arc<dyn Fn(&Event) + Send + Sync>);
+
+impl fmt::Debug for Observer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Observer")
+    }
+}
+
+static FILES: AtomicUsize = AtomicUsize::new(0);
+static DIRS: AtomicUsize = AtomicUsize::new(0);
+static ERRORS: AtomicUsize = AtomicUsize::new(0);
+static BYTES: AtomicU64 = AtomicU64::new(0);
+
+/// Number of errors emitted so far in this process.
+pub fn error_count() -> usize {
+    ERRORS.load(Ordering::SeqCst)
+}
+
+/// Records `event` in the journal, counts it for the summary, passes it to the observer and
+/// prints it.
+pub fn emit(config: &Config, event: Event) {
+    journal::record(config, &event);
+
+    match event {
+        Event::Done { bytes, .. } |
+        Event::WouldShred { bytes, .. } => {
+            FILES.fetch_add(1, Ordering::SeqCst);
+            BYTES.fetch_add(bytes, Ordering::SeqCst);
+        }
+        Event::WouldRemove { dir: true, .. } => {
+            DIRS.fetch_add(1, Ordering::SeqCst);
+        }
+        Event::Error { .. } => {
+            ERRORS.fetch_add(1, Ordering::SeqCst);
+        }
+        _ => {}
+    }
+
+    if let Some(ref observer) = config.observer {
+        (observer.0)(&event);
+    }
+
+    match config.output {
+        OutputFormat::None => {}
+        OutputFormat::Text => {
+            if let Some((stderr, line)) = to_text(config, &event) {
+                print(stderr, line);
+            }
+        }
+        OutputFormat::Json => {
+            if let Some(line) = to_json(config, &event) {
+                print(false, line);
+            }
+        }
+    }
+}
+
+/// Time that overwriting `bytes` with every pass is expected to take at `--throughput`.
+fn estimate(config: &Config, bytes: u64) -> Duration {
+    let passes = method::passes(config).len();
+    Duration::from_secs_f64(bytes as f64 * passes as f64 / config.throughput)
+}
+
+/// The `-v` line for `event`, if any, and whether it goes to stderr.
+fn to_text(config: &Config, event: &Event) -> Option<(bool, String)> {
+    // Dry runs are reported regardless of -v; that is their whole output.
+    let line = match *event {
+        Event::Error { path, error } => {
+            return Some((true, format!("shrem: cannot remove '{}': {}", path.display(), error)));
+        }
+        Event::WouldShred { path, bytes } => {
+            let action = if config.no_remove { "overwrite" } else { "overwrite and remove" };
+            format!("shrem: would {} '{}' ({})", action, path.display(), format_bytes(bytes))
+        }
+        Event::WouldRemove { path, dir: true } => {
+            format!("shrem: would rename and remove directory '{}'", path.display())
+        }
+        Event::WouldRemove { path, dir: false } => {
+            format!("shrem: would remove '{}'", path.display())
+        }
+        Event::Summary { .. } if config.dry_run => {
+            let bytes = BYTES.load(Ordering::SeqCst);
+            format!("shrem: {} files ({}), {} directories; estimated {} at {:.0} MB/s",
+                    FILES.load(Ordering::SeqCst),
+                    format_bytes(bytes),
+                    DIRS.load(Ordering::SeqCst),
+                    format_duration(estimate(config, bytes)),
+                    config.throughput / 1_000_000.0)
+        }
+        Event::HardLinked { path, links } if config.hard_links == HardLinks::Ask => {
+            return Some((true,
+                         format!("shrem: warning: '{}' has {} other hard links, which share its \
+                                  data",
+                                 path.display(),
+                                 links - 1)));
+        }
+        Event::CopyOnWrite { path, filesystem, shared } if config.on_cow == OnCow::Warn => {
+            let line = format!("shrem: warning: {}", copy_on_write(path, filesystem, shared));
+            return Some((true, line));
+        }
+        Event::FilesystemAdvisory { mount_point, fstype, advice, .. } => {
+            return Some((true,
+                         format!("shrem: note: {} ({}) {}", mount_point.display(), fstype, advice)));
+        }
+        Event::ExtentsChecked { path, pass, relocated: true } => {
+            return Some((true,
+                         format!("shrem: warning: {}: pass {} was written to other blocks; the \
+                                  original ones may still hold the data",
+                                 path.display(),
+                                 pass)));
+        }
+        _ if !config.verbose => return None,
+        Event::Start { path, .. } => {
+            let method = config.method?;
+            format!("shrem: {}: using {} ({} passes)",
+                    path.display(),
+                    method.description,
+                    method.passes.len())
+        }
+        Event::PassStart { path, pass, total, pattern } => {
+            format!("shrem: {}: pass {}/{} ({})...", path.display(), pass, total, pattern)
+        }
+        Event::ExtentsChecked { path, pass, .. } => {
+            format!("shrem: {}: pass {}: extents in place", path.display(), pass)
+        }
+        Event::Overlay { path, known: false, .. } => {
+            format!("shrem: {}: layer unknown: the layers of the overlay cannot be looked at, so \
+                     a lower one may keep the data",
+                    path.display())
+        }
+        Event::Overlay { path, upper: Some(upper), .. } => {
+            format!("shrem: {}: in the upper layer at {}", path.display(), upper.display())
+        }
+        Event::Overlay { path, lower: Some(lower), .. } => {
+            format!("shrem: {}: in a lower layer at {}", path.display(), lower.display())
+        }
+        Event::Verifying { path } => format!("shrem: {}: verifying", path.display()),
+        Event::Verified { path, .. } => format!("shrem: {}: verified", path.display()),
+        Event::Removing { path } => format!("shrem: {}: removing", path.display()),
+        Event::Renamed { from, to } => {
+            format!("shrem: {}: renamed to {}", from.display(), to.display())
+        }
+        Event::Removed { path } => format!("shrem: {}: removed", path.display()),
+        Event::ForensicVerified { path, device, samples: 0 } => {
+            format!("shrem: {}: nothing to look for on {}", path.display(), device.display())
+        }
+        Event::ForensicVerified { path, device, samples } => {
+            format!("shrem: {}: none of {} samples left on {}",
+                    path.display(),
+                    samples,
+                    device.display())
+        }
+        Event::HardLinked { path, links } => {
+            format!("shrem: {}: {} other hard links", path.display(), links - 1)
+        }
+        Event::Skipped { path } => format!("shrem: {}: skipped", path.display()),
+        Event::CopyOnWrite { path, filesystem, shared } => {
+            format!("shrem: {}", copy_on_write(path, filesystem, shared))
+        }
+        _ => return None,
+    };
+
+    Some((false, line))
+}
+
+fn copy_on_write(path: &Path, filesystem: Option<&str>, shared: bool) -> String {
+    match filesystem {
+        _ if shared => {
+            format!("'{}' shares its blocks with other files or snapshots, which keep the old data",
+                    path.display())
+        }
+        Some(filesystem) => {
+            format!("'{}' is on {}, which writes new blocks instead of overwriting the old ones",
+                    path.display(),
+                    filesystem)
+        }
+        None => format!("'{}' is on copy-on-write storage", path.display()),
+    }
+}
+
+/// A printed line held back while its file is being shredded on a worker thread.
+pub(crate) struct Line {
+    stderr: bool,
+    text: String,
+}
+
+impl Line {
+    pub(crate) fn print(&self) {
+        if self.stderr {
+            eprintln!("{}", self.text);
+        } else {
+            let stdout = io::stdout();
+            let _ = writeln!(stdout.lock(), "{}", self.text);
+        }
+    }
+}
+
+thread_local! {
+    static CAPTURE: RefCell<Option<Vec<Line>>> = const { RefCell::new(None) };
+}
+
+/// Runs `f`, collecting the lines it would print instead of printing them, so that the output
+/// of concurrently shredded files does not interleave.
+pub(crate) fn capture<F, R>(f: F) -> (R, Vec<Line>)
+    where F: FnOnce() -> R
+{
+    CAPTURE.with(|c| *c.borrow_mut() = Some(Vec::new()));
+    let result = f();
+    let lines = CAPTURE.with(|c| c.borrow_mut().take()).unwrap_or_default();
+    (result, lines)
+}
+
+fn print(stderr: bool, text: String) {
+    let line = Line { stderr, text };
+    CAPTURE.with(|c| match *c.borrow_mut() {
+        Some(ref mut lines) => lines.push(line),
+        None => line.print(),
+    });
+}
+
+fn to_json(config: &Config, event: &Event) -> Option<String> {
+    let mut json = Json::new();
+
+    match *event {
+        Event::Start { path, bytes } => {
+            json.str("event", "start").path("path", path).num("bytes", bytes);
+            if let Some(method) = config.method {
+                json.str("method", method.name);
+            }
+        }
+        Event::PassStart { path, pass, total, pattern } => {
+            json.str("event", "pass_start")
+                .path("path", path)
+                .num("pass", pass as u64)
+                .num("total", total as u64)
+                .str("pattern", pattern);
+        }
+        Event::Progress { .. } => return None,
+        Event::PassDone { path, pass, bytes, duration } => {
+            json.str("event", "pass_done")
+                .path("path", path)
+                .num("pass", pass as u64)
+                .num("bytes", bytes)
+                .duration("duration", duration);
+        }
+        Event::FilesystemAdvisory { mount_point, fstype, source, advice } => {
+            json.str("event", "filesystem_advisory")
+                .path("mount_point", mount_point)
+                .str("fstype", fstype)
+                .str("source", source)
+                .str("advice", advice);
+        }
+        Event::Overlay { path, upper, lower, known } => {
+            json.str("event", "overlay").path("path", path).bool("known", known);
+            if let Some(upper) = upper {
+                json.path("upper", upper);
+            }
+            if let Some(lower) = lower {
+                json.path("lower", lower);
+            }
+        }
+        Event::ExtentsChecked { path, pass, relocated } => {
+            json.str("event", "extents_checked")
+                .path("path", path)
+                .num("pass", pass as u64)
+                .bool("relocated", relocated);
+        }
+        Event::Verifying { path } => {
+            json.str("event", "verifying").path("path", path);
+        }
+        Event::Verified { path, duration } => {
+            json.str("event", "verified").path("path", path).duration("duration", duration);
+        }
+        Event::Removing { path } => {
+            json.str("event", "removing").path("path", path);
+        }
+        Event::Renamed { from, to } => {
+            json.str("event", "renamed").path("from", from).path("to", to);
+        }
+        Event::Removed { path } => {
+            json.str("event", "removed").path("path", path);
+        }
+        Event::HardLinked { path, links } => {
+            json.str("event", "hard_linked").path("path", path).num("links", links);
+        }
+        Event::CopyOnWrite { path, filesystem, shared } => {
+            json.str("event", "copy_on_write").path("path", path).bool("shared", shared);
+            if let Some(filesystem) = filesystem {
+                json.str("filesystem", filesystem);
+            }
+        }
+        Event::Skipped { path } => {
+            json.str("event", "skipped").path("path", path);
+        }
+        Event::ForensicVerified { path, device, samples } => {
+            json.str("event", "forensic_verified")
+                .path("path", path)
+                .path("device", device)
+                .num("samples", samples as u64);
+        }
+        Event::Done { path, bytes, duration } => {
+            json.str("event", "done")
+                .path("path", path)
+                .num("bytes", bytes)
+                .duration("duration", duration);
+        }
+        Event::Error { path, error } => {
+            json.str("event", "error")
+                .path("path", path)
+                .str("kind", error.kind())
+                .str("message", &error.to_string());
+        }
+        Event::WouldShred { path, bytes } => {
+            json.str("event", "would_shred").path("path", path).num("bytes", bytes);
+        }
+        Event::WouldRemove { path, dir } => {
+            json.str("event", "would_remove")
+                .path("path", path)
+                .str("type", if dir { "directory" } else { "file" });
+        }
+        Event::Summary { duration } => {
+            let bytes = BYTES.load(Ordering::SeqCst);
+            json.str("event", "summary")
+                .num("files", FILES.load(Ordering::SeqCst) as u64)
+                .num("bytes", bytes)
+                .num("errors", error_count() as u64)
+                .duration("duration", duration);
+            if config.dry_run {
+                json.num("directories", DIRS.load(Ordering::SeqCst) as u64)
+                    .duration("estimated", estimate(config, bytes));
+            }
+        }
+    }
+
+    Some(json.finish())
+}
+
+/// Formats a byte count with a decimal unit, e.g. `1.5 GB`.
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: &[&str] = &["kB", "MB", "GB", "TB", "PB"];
+
+    if bytes < 1000 {
+        return format!("{} bytes", bytes);
+    }
+
+    let mut value = bytes as f64 / 1000.0;
+    let mut unit = 0;
+    while value >= 1000.0 && unit + 1 < UNITS.len() {
+        value /= 1000.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+/// Formats a duration as `H:MM:SS`.
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    format!("{}:{:02}:{:02}", secs / 3600, secs % 3600 / 60, secs % 60)
+}
+
+/// Minimal writer for a flat JSON object.
+pub(crate) struct Json {
+    buf: String,
+}
+
+impl Json {
+    pub fn new() -> Json {
+        Json { buf: String::from("{") }
+    }
+
+    fn key(&mut self, key: &str) {
+        if self.buf.len() > 1 {
+            self.buf.push(',');
+        }
+        write_escaped(&mut self.buf, key);
+        self.buf.push(':');
+    }
+
+    pub fn str(&mut self, key: &str, value: &str) -> &mut Json {
+        self.key(key);
+        write_escaped(&mut self.buf, value);
+        self
+    }
+
+    /// Paths that are not valid UTF-8 are written lossily.
+    pub fn path(&mut self, key: &str, value: &Path) -> &mut Json {
+        self.str(key, &value.to_string_lossy())
+    }
+
+    pub fn num(&mut self, key: &str, value: u64) -> &mut Json {
+        self.key(key);
+        let _ = write!(self.buf, "{}", value);
+        self
+    }
+
+    pub fn bool(&mut self, key: &str, value: bool) -> &mut Json {
+        self.key(key);
+        self.buf.push_str(if value { "true" } else { "false" });
+        self
+    }
+
+    /// Embeds `value`, which must already be valid JSON.
+    pub fn raw(&mut self, key: &str, value: &str) -> &mut Json {
+        self.key(key);
+        self.buf.push_str(value);
+        self
+    }
+
+    /// Durations are written as fractional seconds.
+    pub fn duration(&mut self, key: &str, value: Duration) -> &mut Json {
+        self.key(key);
+        let _ = write!(self.buf, "{:.6}", value.as_secs_f64());
+        self
+    }
+
+    pub fn finish(mut self) -> String {
+        self.buf.push('}');
+        self.buf
+    }
+}
+
+fn write_escaped(buf: &mut String, s: &str) {
+    buf.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => buf.push_str("\\\""),
+            '\\' => buf.push_str("\\\\"),
+            '\n' => buf.push_str("\\n"),
+            '\r' => buf.push_str("\\r"),
+            '\t' => buf.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(buf, "\\u{:04x}", c as u32);
+            }
+            c => buf.push(c),
+        }
+    }
+    buf.push('"');
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{to_json, to_text, Event};
+    use {Config, OnCow};
+
+    #[test]
+    fn copy_on_write() {
+        let path = Path::new("/a/b");
+        let shared = Event::CopyOnWrite { path, filesystem: None, shared: true };
+        let btrfs = Event::CopyOnWrite { path, filesystem: Some("btrfs"), shared: false };
+
+        let config = Config::default();
+        assert_eq!(to_json(&config, &shared).unwrap(),
+                   r#"{"event":"copy_on_write","path":"/a/b","shared":true}"#);
+        assert_eq!(to_json(&config, &btrfs).unwrap(),
+                   concat!(r#"{"event":"copy_on_write","path":"/a/b","shared":false,"#,
+                           r#""filesystem":"btrfs"}"#));
+        assert_eq!(to_text(&config, &btrfs),
+                   Some((true,
+                         "shrem: warning: '/a/b' is on btrfs, which writes new blocks instead of \
+                          overwriting the old ones"
+                             .to_owned())));
+
+        // Without the warning, only printed with -v.
+        let config = Config { on_cow: OnCow::Proceed, ..Config::default() };
+        assert_eq!(to_text(&config, &shared), None);
+        let config = Config { verbose: true, ..config };
+        assert_eq!(to_text(&config, &shared),
+                   Some((false,
+                         "shrem: '/a/b' shares its blocks with other files or snapshots, which \
+                          keep the old data"
+                             .to_owned())));
+    }
+
+    #[test]
+    fn extents_checked() {
+        let path = Path::new("/a/b");
+        let in_place = Event::ExtentsChecked { path, pass: 1, relocated: false };
+        let relocated = Event::ExtentsChecked { path, pass: 2, relocated: true };
+
+        let config = Config::default();
+        assert_eq!(to_json(&config, &relocated).unwrap(),
+                   r#"{"event":"extents_checked","path":"/a/b","pass":2,"relocated":true}"#);
+        assert_eq!(to_text(&config, &in_place), None);
+        assert_eq!(to_text(&config, &relocated),
+                   Some((true,
+                         "shrem: warning: /a/b: pass 2 was written to other blocks; the original \
+                          ones may still hold the data"
+                             .to_owned())));
+
+        let config = Config { verbose: true, ..Config::default() };
+        assert_eq!(to_text(&config, &in_place),
+                   Some((false, "shrem: /a/b: pass 1: extents in place".to_owned())));
+    }
+}
@@ -0,0 +1,205 @@
+//! Where the data of a file is on the device: its extents as the `FIEMAP` ioctl reports them, and
+//! whether the filesystem writes changed data to new blocks (copy on write) instead of in place.
+//!
+//! Both are only known on Linux. Elsewhere, and on filesystems without `FIEMAP`, the extents are
+//! an error and no filesystem is copy-on-write.
+
+use std::fs::File;
+use std::io;
+use std::sync::Mutex;
+
+/// A run of the file that is stored in consecutive bytes of the device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Extent {
+    /// Offset in the file.
+    pub logical: u64,
+    /// Offset on the device.
+    pub physical: u64,
+    pub length: u64,
+    pub flags: u32,
+}
+
+impl Extent {
+    /// Whether other files or snapshots use the same blocks.
+    pub fn is_shared(&self) -> bool {
+        self.flags & FIEMAP_EXTENT_SHARED != 0
+    }
+}
+
+#[cfg(target_os = "linux")]
+const FIEMAP_EXTENT_LAST: u32 = 0x1;
+const FIEMAP_EXTENT_SHARED: u32 = 0x2000;
+/// Flush the file first, so that delayed allocations have their place.
+#[cfg(target_os = "linux")]
+const FIEMAP_FLAG_SYNC: u32 = 0x1;
+#[cfg(target_os = "linux")]
+const FS_IOC_FIEMAP: libc::c_ulong = 0xC020_660B;
+
+/// Number of extents asked for at a time.
+#[cfg(target_os = "linux")]
+const BATCH: usize = 256;
+
+#[cfg(target_os = "linux")]
+#[repr(C)]
+struct Fiemap {
+    start: u64,
+    length: u64,
+    flags: u32,
+    mapped_extents: u32,
+    extent_count: u32,
+    reserved: u32,
+    extents: [FiemapExtent; BATCH],
+}
+
+#[cfg(target_os = "linux")]
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct FiemapExtent {
+    logical: u64,
+    physical: u64,
+    length: u64,
+    reserved64: [u64; 2],
+    flags: u32,
+    reserved: [u32; 3],
+}
+
+/// Filesystems that never overwrite data in place, by the magic number `statfs` reports.
+#[cfg(target_os = "linux")]
+const COW_FILESYSTEMS: &[(u32, &str)] = &[
+    (0x9123_683E, "btrfs"),
+    (0x2FC1_2FC1, "zfs"),
+    (0xCA45_1A4E, "bcachefs"),
+];
+
+/// The extents of `file`, in file order.
+#[cfg(target_os = "linux")]
+pub(crate) fn extents(file: &File) -> io::Result<Vec<Extent>> {
+    use std::os::unix::io::AsRawFd;
+
+    let mut extents = Vec::new();
+    let mut start = 0;
+    loop {
+        let mut map = Box::new(unsafe { std::mem::zeroed::<Fiemap>() });
+        map.start = start;
+        map.length = u64::MAX - start;
+        map.flags = FIEMAP_FLAG_SYNC;
+        map.extent_count = BATCH as u32;
+        if unsafe { libc::ioctl(file.as_raw_fd(), FS_IOC_FIEMAP as _, &mut *map) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mapped = &map.extents[..map.mapped_extents as usize];
+        extents.extend(mapped.iter().map(|e| {
+            Extent {
+                logical: e.logical,
+                physical: e.physical,
+                length: e.length,
+                flags: e.flags,
+            }
+        }));
+        match mapped.last() {
+            Some(last) if last.flags & FIEMAP_EXTENT_LAST == 0 => {
+                start = last.logical + last.length;
+            }
+            _ => return Ok(extents),
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn extents(_file: &File) -> io::Result<Vec<Extent>> {
+    Err(io::Error::from_raw_os_error(libc::EOPNOTSUPP))
+}
+
+/// Whether overwriting a file left its data in the blocks it was in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Placement {
+    InPlace,
+    /// The filesystem wrote some of the new data elsewhere; the original blocks may still hold
+    /// the old data.
+    Relocated,
+}
+
+/// The extents of `file` with adjacent ones merged and without flags, for comparing where its
+/// data is at different times.
+pub(crate) fn layout(file: &File) -> io::Result<Vec<Extent>> {
+    Ok(merge(extents(file)?))
+}
+
+/// `extents` with those that continue each other, in the file and on the device, merged into one
+/// and without flags. How the filesystem splits up a run may change when it is rewritten.
+fn merge(extents: Vec<Extent>) -> Vec<Extent> {
+    let mut layout: Vec<Extent> = Vec::new();
+    for extent in extents {
+        match layout.last_mut() {
+            Some(last) if last.logical + last.length == extent.logical &&
+                          last.physical + last.length == extent.physical => {
+                last.length += extent.length;
+            }
+            _ => layout.push(Extent { flags: 0, ..extent }),
+        }
+    }
+    layout
+}
+
+/// The name of the filesystem `file` is on if it is copy-on-write.
+#[cfg(target_os = "linux")]
+pub(crate) fn cow_filesystem(file: &File) -> io::Result<Option<&'static str>> {
+    use std::os::unix::io::AsRawFd;
+
+    let mut stat = unsafe { std::mem::zeroed::<libc::statfs>() };
+    if unsafe { libc::fstatfs(file.as_raw_fd(), &mut stat) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    // `f_type` is signed on some platforms; the magic numbers are 32 bits either way.
+    let magic = stat.f_type as u32;
+    Ok(COW_FILESYSTEMS.iter().find(|&&(m, _)| m == magic).map(|&(_, name)| name))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn cow_filesystem(_file: &File) -> io::Result<Option<&'static str>> {
+    Ok(None)
+}
+
+/// Whether `dev` has not been asked about before, for advice that is given once per filesystem.
+pub(crate) fn first_on(dev: u64) -> bool {
+    static SEEN: Mutex<Vec<u64>> = Mutex::new(Vec::new());
+
+    let mut seen = SEEN.lock().unwrap();
+    if seen.contains(&dev) {
+        false
+    } else {
+        seen.push(dev);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{merge, Extent};
+
+    #[test]
+    fn shared_extents() {
+        let extent = Extent { logical: 0, physical: 4096, length: 4096, flags: 0x1 };
+        assert!(!extent.is_shared());
+        assert!(Extent { flags: 0x2000 | 0x1, ..extent }.is_shared());
+    }
+
+    #[test]
+    fn adjacent_extents_are_merged() {
+        let extent = |logical, physical, length, flags| Extent { logical, physical, length, flags };
+        assert_eq!(merge(vec![extent(0, 8192, 4096, 0),
+                              extent(4096, 12288, 4096, 0x800),
+                              extent(8192, 16384, 4096, 0x1)]),
+                   [extent(0, 8192, 12288, 0)]);
+
+        // A gap in the file or on the device starts another extent.
+        assert_eq!(merge(vec![extent(0, 8192, 4096, 0),
+                              extent(4096, 65536, 4096, 0),
+                              extent(12288, 69632, 4096, 0x1)]),
+                   [extent(0, 8192, 4096, 0),
+                    extent(4096, 65536, 4096, 0),
+                    extent(12288, 69632, 4096, 0)]);
+        assert!(merge(Vec::new()).is_empty());
+    }
+}
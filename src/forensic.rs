@@ -0,0 +1,234 @@
+//! `--forensic-verify`: checking on the block device that the original data of a file is gone.
+//!
+//! Before a file is overwritten, a few blocks of it are read and their digests kept, together with
+//! where they are on the device according to `FIEMAP`. Once the file has been shredded, the block
+//! device is read at those places; finding any of the digests there means the filesystem left the
+//! original data behind.
+//!
+//! Blocks that hold a single repeated byte are not sampled, since a pass may write the same. This
+//! only works on Linux, on filesystems that map files to the block device they are on, which rules
+//! out copy-on-write filesystems, where `FIEMAP` reports addresses of their own.
+
+use std::fs::File;
+use std::io;
+use std::os::unix::fs::FileExt;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+
+use super::{Config, ShremError};
+use event::{self, Event};
+use extents::{self, Extent};
+use sha256::Sha256;
+
+/// Size of a sample.
+const SAMPLE_SIZE: u64 = 4096;
+/// Number of samples spread over a file.
+const SAMPLES: u64 = 8;
+
+/// Flags of extents whose data cannot be read from the device as it is: unknown location,
+/// delayed allocation, encoded, encrypted, not aligned, inline and unwritten.
+const UNREADABLE: u32 = 0x2 | 0x4 | 0x8 | 0x80 | 0x100 | 0x200 | 0x800;
+
+struct Sample {
+    /// Offset on the device.
+    physical: u64,
+    length: u64,
+    digest: [u8; 32],
+}
+
+/// Samples of the original contents of a file and the device they were read from.
+pub(crate) struct Samples {
+    device: PathBuf,
+    /// Opened before anything is overwritten, so that missing permissions fail early.
+    file: File,
+    samples: Vec<Sample>,
+}
+
+impl Samples {
+    /// Takes samples of `file`, which must have been opened for reading and is on device `dev`.
+    pub fn take(file: &File, dev: u64) -> Result<Samples, ShremError> {
+        if extents::cow_filesystem(file)?.is_some() {
+            return Err(ShremError::Unsupported("--forensic-verify on a copy-on-write filesystem"));
+        }
+        let device = block_device(dev)?;
+        let device_file = File::open(&device)?;
+
+        let len = file.metadata()?.len();
+        let extents = extents::extents(file).map_err(|e| {
+            if e.raw_os_error() == Some(libc::EOPNOTSUPP) {
+                ShremError::Unsupported("--forensic-verify on a filesystem without FIEMAP")
+            } else {
+                e.into()
+            }
+        })?;
+        let extents = extents.into_iter()
+            .filter(|extent| extent.flags & UNREADABLE == 0 && extent.logical < len)
+            .collect::<Vec<_>>();
+
+        let total = extents.iter().map(|extent| readable(extent, len)).sum::<u64>();
+        let mut samples = Vec::new();
+        let mut buf = vec![0; SAMPLE_SIZE as usize];
+        let mut last = None;
+        for i in 0..SAMPLES {
+            // Offset into the readable bytes, aligned to a sample.
+            let mut offset = total * i / SAMPLES / SAMPLE_SIZE * SAMPLE_SIZE;
+            let extent = match extents.iter().find(|extent| {
+                let n = readable(extent, len);
+                if offset < n {
+                    true
+                } else {
+                    offset -= n;
+                    false
+                }
+            }) {
+                Some(extent) => extent,
+                None => break,
+            };
+
+            let logical = extent.logical + offset;
+            if last == Some(logical) {
+                continue;
+            }
+            last = Some(logical);
+
+            let length = SAMPLE_SIZE.min(readable(extent, len) - offset);
+            let buf = &mut buf[..length as usize];
+            file.read_exact_at(buf, logical)?;
+            if buf.iter().all(|&b| b == buf[0]) {
+                continue;
+            }
+            samples.push(Sample {
+                physical: extent.physical + offset,
+                length,
+                digest: digest(buf),
+            });
+        }
+
+        Ok(Samples {
+            device,
+            file: device_file,
+            samples,
+        })
+    }
+
+    /// Reads the device where the samples were and fails if any of them is still there.
+    pub fn check(&self, path: &Path, config: &Config) -> Result<(), ShremError> {
+        // Blocks cached from before would hide what is on the device now.
+        let ret = unsafe {
+            libc::posix_fadvise(self.file.as_raw_fd(), 0, 0, libc::POSIX_FADV_DONTNEED)
+        };
+        if ret != 0 {
+            return Err(io::Error::from_raw_os_error(ret).into());
+        }
+
+        let mut buf = vec![0; SAMPLE_SIZE as usize];
+        for sample in &self.samples {
+            let buf = &mut buf[..sample.length as usize];
+            self.file.read_exact_at(buf, sample.physical)?;
+            if digest(buf) == sample.digest {
+                return Err(ShremError::DataRemains(sample.physical));
+            }
+        }
+
+        event::emit(config,
+                    Event::ForensicVerified {
+                        path,
+                        device: &self.device,
+                        samples: self.samples.len(),
+                    });
+        Ok(())
+    }
+}
+
+/// The bytes of `extent` that are part of a file of `len` bytes.
+fn readable(extent: &Extent, len: u64) -> u64 {
+    extent.length.min(len - extent.logical)
+}
+
+fn digest(data: &[u8]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(data);
+    hasher.finish()
+}
+
+/// The block device node of device number `dev`, as named in sysfs.
+#[cfg(target_os = "linux")]
+fn block_device(dev: u64) -> Result<PathBuf, ShremError> {
+    use std::fs;
+
+    let uevent = format!("/sys/dev/block/{}:{}/uevent", libc::major(dev), libc::minor(dev));
+    fs::read_to_string(uevent)
+        .ok()
+        .and_then(|uevent| {
+            uevent.lines()
+                .find_map(|line| line.strip_prefix("DEVNAME="))
+                .map(|name| Path::new("/dev").join(name))
+        })
+        .ok_or(ShremError::Unsupported("--forensic-verify on a filesystem without a block device"))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn block_device(_dev: u64) -> Result<PathBuf, ShremError> {
+    Err(ShremError::Unsupported("--forensic-verify on a system other than Linux"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs::{self, File};
+    use std::process;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use super::{digest, readable, Sample, Samples, SAMPLE_SIZE};
+    use event::{Event, Observer};
+    use extents::Extent;
+    use {Config, ShremError};
+
+    #[test]
+    fn samples_are_looked_for_on_the_device() {
+        // A file standing in for the device, with the original data in its second block.
+        let path = env::temp_dir().join(format!("shrem-test-forensic-{}", process::id()));
+        let original = (0..SAMPLE_SIZE).map(|i| i as u8).collect::<Vec<_>>();
+        let mut device = vec![0; SAMPLE_SIZE as usize];
+        device.extend_from_slice(&original);
+        fs::write(&path, &device).unwrap();
+
+        let samples = |physical| {
+            Samples {
+                device: path.clone(),
+                file: File::open(&path).unwrap(),
+                samples: vec![Sample { physical, length: SAMPLE_SIZE, digest: digest(&original) }],
+            }
+        };
+        let verified = Arc::new(AtomicUsize::new(0));
+        let config = Config {
+            observer: Some({
+                let verified = verified.clone();
+                Observer(Arc::new(move |event: &Event| {
+                    if let Event::ForensicVerified { samples, .. } = *event {
+                        verified.fetch_add(samples, Ordering::SeqCst);
+                    }
+                }))
+            }),
+            ..Config::default()
+        };
+
+        match samples(SAMPLE_SIZE).check(&path, &config) {
+            Err(ShremError::DataRemains(offset)) => assert_eq!(offset, SAMPLE_SIZE),
+            result => panic!("{:?}", result),
+        }
+        assert_eq!(verified.load(Ordering::SeqCst), 0);
+
+        samples(0).check(&path, &config).unwrap();
+        assert_eq!(verified.load(Ordering::SeqCst), 1);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn readable_part_of_the_last_extent() {
+        let extent = Extent { logical: 8192, physical: 0, length: 8192, flags: 0 };
+        assert_eq!(readable(&extent, 20000), 8192);
+        assert_eq!(readable(&extent, 10000), 1808);
+    }
+}
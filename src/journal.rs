@@ -0,0 +1,721 @@
+//! Append-only journal of files and directories being shredded, so that interrupted jobs can be
+//! finished with `--resume`.
+//!
+//! Each line is one record; the path is always the last field:
+//!
+//! ```text
+//! start ID file SCHEME DEV:INO:SIZE PATH   a file is about to be overwritten with SCHEME
+//! start ID dir - DEV:INO:SIZE PATH         a directory is about to be renamed and removed
+//! start ID link - DEV:INO:SIZE PATH        another name of a file that has been overwritten
+//!                                          under an earlier one is about to be removed
+//! start ID tree SCHEME DEV:INO:SIZE PATH   a directory tree given as an argument is about to
+//!                                          be shredded
+//! pass ID N                                pass N has been written and flushed
+//! rename ID NAME                           the entry has been renamed to NAME, in the same
+//!                                          directory
+//! done ID                                  the entry is gone (or, with --no-remove, overwritten)
+//! ```
+//!
+//! Paths are absolute. `DEV:INO:SIZE` identifies the entry, so that `--resume` leaves alone
+//! whatever has taken its place since; it is `-` if the entry could not be looked at. Bytes that
+//! would break the format (whitespace, control characters and `%`) are written as `%XX`. Every
+//! record is flushed to disk before shredding goes on; a last line without its newline was cut
+//! off and is ignored.
+//!
+//! The original paths of the entries being shredded are in the journal as long as a run is in
+//! progress, which is what `--resume` needs to find them. Records are derived from events. A
+//! process using the journal holds a shared `flock` on it. When the last one finishes and no job
+//! is unfinished, the journal is overwritten with zeros, emptied and removed, so that the names
+//! do not outlive the run.
+
+use std::collections::HashMap;
+use std::env;
+use std::ffi::OsStr;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Seek, SeekFrom, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::{check_interrupt, remove_dir_at, remove_link_at, report_error, shred_file_at, Config,
+            ShremError};
+use at::{Entry, Stat};
+use backend::BackendKind;
+use event::Event;
+use method;
+
+/// An entry whose shredding was started but not finished.
+#[derive(Debug, Clone)]
+pub struct Job {
+    pub id: String,
+    pub kind: JobKind,
+    /// How the file was being overwritten: `method:NAME` or `random:N`, with `:zero` if a final
+    /// zero pass was added. `-` for directories.
+    pub scheme: String,
+    pub original: PathBuf,
+    /// Where the entry is now; differs from `original` once renaming has started.
+    pub current: PathBuf,
+    /// Passes that were completed.
+    pub passes: usize,
+    /// The device and inode number of the entry, and its size when the job was started. `None`
+    /// if the journal does not say.
+    pub identity: Option<(u64, u64, u64)>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum JobKind {
+    File,
+    /// An empty directory being renamed and removed.
+    Dir,
+    /// Another name of a file that has been overwritten under an earlier one, being renamed and
+    /// removed.
+    Link,
+    /// A whole tree, of which the files and directories not shredded yet are left.
+    Tree,
+}
+
+struct Journal {
+    path: PathBuf,
+    file: File,
+    /// Open jobs by the path their events refer to.
+    jobs: HashMap<PathBuf, String>,
+    /// Paths that entries have been renamed to, mapped to the path of their job.
+    renamed: HashMap<PathBuf, PathBuf>,
+    count: usize,
+}
+
+static JOURNAL: Mutex<Option<Journal>> = Mutex::new(None);
+
+/// `$XDG_STATE_HOME/shrem/journal`, or `~/.local/state/shrem/journal`.
+pub fn default_path() -> Option<PathBuf> {
+    let dir = env::var_os("XDG_STATE_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".local/state")))?;
+    Some(dir.join("shrem").join("journal"))
+}
+
+/// Starts recording to the journal at `path` for the rest of the process, creating it if needed.
+/// With `exclusive`, fails if another process is using the journal.
+pub fn open(path: &Path, exclusive: bool) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::DirBuilder::new().recursive(true).mode(0o700).create(dir)?;
+    }
+
+    let file = loop {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .mode(0o600)
+            .open(path)?;
+
+        if flock(&file, libc::LOCK_EX | libc::LOCK_NB).is_ok() {
+            if read_jobs(&mut file)?.is_empty() {
+                wipe(&mut file)?;
+            }
+            if !exclusive {
+                flock(&file, libc::LOCK_SH)?;
+            }
+        } else if exclusive {
+            return Err(io::Error::new(io::ErrorKind::WouldBlock,
+                                      "the journal is in use by another shrem process"));
+        } else {
+            flock(&file, libc::LOCK_SH)?;
+        }
+
+        // The process that held the lock may have removed the journal in the meantime.
+        if is_at(&file, path) {
+            break file;
+        }
+    };
+
+    *JOURNAL.lock().unwrap() = Some(Journal {
+        path: path.to_path_buf(),
+        file,
+        jobs: HashMap::new(),
+        renamed: HashMap::new(),
+        count: 0,
+    });
+    Ok(())
+}
+
+/// Stops recording. If no other process is using the journal and no job in it is unfinished, it
+/// is emptied and removed.
+pub fn close() -> io::Result<()> {
+    let mut journal = match JOURNAL.lock().unwrap().take() {
+        Some(journal) => journal,
+        None => return Ok(()),
+    };
+
+    if flock(&journal.file, libc::LOCK_EX | libc::LOCK_NB).is_ok() &&
+       read_jobs(&mut journal.file)?.is_empty() {
+        wipe(&mut journal.file)?;
+        fs::remove_file(&journal.path)?;
+    }
+    // Closing the file releases the lock.
+    Ok(())
+}
+
+/// Overwrites the records in `file` with zeros and empties it, so that the paths in them do not
+/// stay behind in freed blocks.
+fn wipe(file: &mut File) -> io::Result<()> {
+    // Writes to a file opened for appending would go to its end.
+    let fd = file.as_raw_fd();
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+    if flags == -1 || unsafe { libc::fcntl(fd, libc::F_SETFL, flags & !libc::O_APPEND) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let zeros = [0; 8192];
+    let mut left = file.metadata()?.len();
+    file.seek(SeekFrom::Start(0))?;
+    while left > 0 {
+        let n = left.min(zeros.len() as u64);
+        file.write_all(&zeros[..n as usize])?;
+        left -= n;
+    }
+    file.sync_all()?;
+    file.set_len(0)?;
+    file.sync_all()?;
+
+    let result = unsafe { libc::fcntl(fd, libc::F_SETFL, flags) };
+    if result == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Whether `path` is still the name of `file`.
+fn is_at(file: &File, path: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    match (file.metadata(), fs::metadata(path)) {
+        (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+        _ => false,
+    }
+}
+
+fn flock(file: &File, operation: libc::c_int) -> io::Result<()> {
+    if unsafe { libc::flock(file.as_raw_fd(), operation) } == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+/// The unfinished jobs in the journal at `path`, in the order they were started.
+pub fn unfinished(path: &Path) -> io::Result<Vec<Job>> {
+    match File::open(path) {
+        Ok(mut file) => read_jobs(&mut file),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e),
+    }
+}
+
+fn read_jobs(file: &mut File) -> io::Result<Vec<Job>> {
+    file.seek(SeekFrom::Start(0))?;
+    parse(BufReader::new(file))
+}
+
+fn parse<R: BufRead>(mut reader: R) -> io::Result<Vec<Job>> {
+    let mut jobs: Vec<Job> = Vec::new();
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        if reader.read_until(b'\n', &mut line)? == 0 {
+            break;
+        }
+        // The process died while writing this, the last record; any number in it may be cut off.
+        if line.pop() != Some(b'\n') {
+            break;
+        }
+        let fields = line.split(|&b| b == b' ').collect::<Vec<_>>();
+        let id = match fields.get(1) {
+            Some(id) => String::from_utf8_lossy(id).into_owned(),
+            None => continue,
+        };
+        let job = jobs.iter_mut().find(|job| job.id == id);
+
+        // Records that are cut off (the process died while writing) or unknown are skipped.
+        match (fields[0], job) {
+            // Journals written before entries were identified have no `DEV:INO:SIZE`.
+            (b"start", None) if fields.len() == 5 || fields.len() == 6 => {
+                let kind = match fields[2] {
+                    b"file" => JobKind::File,
+                    b"dir" => JobKind::Dir,
+                    b"link" => JobKind::Link,
+                    b"tree" => JobKind::Tree,
+                    _ => continue,
+                };
+                let identity = if fields.len() == 6 { parse_identity(fields[4]) } else { None };
+                let path = unescape(fields[fields.len() - 1]);
+                jobs.push(Job {
+                    id,
+                    kind,
+                    scheme: String::from_utf8_lossy(fields[3]).into_owned(),
+                    original: path.clone(),
+                    current: path,
+                    passes: 0,
+                    identity,
+                });
+            }
+            (b"pass", Some(job)) if fields.len() == 3 => {
+                if let Some(n) = std::str::from_utf8(fields[2]).ok().and_then(|n| n.parse().ok()) {
+                    job.passes = n;
+                }
+            }
+            (b"rename", Some(job)) if fields.len() == 3 => {
+                // Journals written before names were recorded alone have absolute paths here,
+                // which `join` keeps as they are.
+                let name = unescape(fields[2]);
+                job.current = match job.current.parent() {
+                    Some(dir) => dir.join(name),
+                    None => name,
+                };
+            }
+            (b"done", Some(_)) => jobs.retain(|job| job.id != id),
+            _ => {}
+        }
+    }
+
+    Ok(jobs)
+}
+
+fn parse_identity(field: &[u8]) -> Option<(u64, u64, u64)> {
+    let mut numbers = std::str::from_utf8(field).ok()?.split(':').map(|n| n.parse().ok());
+    match (numbers.next(), numbers.next(), numbers.next(), numbers.next()) {
+        (Some(dev), Some(ino), Some(size), None) => Some((dev?, ino?, size?)),
+        _ => None,
+    }
+}
+
+/// Writes the records for `event`, if the journal is open.
+pub(crate) fn record(config: &Config, event: &Event) {
+    let mut guard = JOURNAL.lock().unwrap();
+    let journal = match *guard {
+        Some(ref mut journal) => journal,
+        None => return,
+    };
+
+    match *event {
+        // Resumed jobs are known already.
+        Event::Start { path, .. } if !journal.jobs.contains_key(path) => {
+            let id = journal.new_id();
+            let stat = Entry::of(path).ok().map(|entry| entry.stat);
+            journal.write(&format!("start {} file {} {} ", id, scheme(config), identity(stat)),
+                          Some(absolute(path).as_os_str()));
+            journal.jobs.insert(path.to_path_buf(), id);
+        }
+        // Directories and other names of overwritten files have no `Start`; their removal is
+        // their whole job.
+        Event::Removing { path } if !journal.jobs.contains_key(path) => {
+            let id = journal.new_id();
+            let stat = Entry::of(path).ok().map(|entry| entry.stat);
+            let kind = match stat {
+                Some(ref stat) if !stat.is_dir() => "link",
+                _ => "dir",
+            };
+            journal.write(&format!("start {} {} - {} ", id, kind, identity(stat)),
+                          Some(absolute(path).as_os_str()));
+            journal.jobs.insert(path.to_path_buf(), id);
+        }
+        Event::PassDone { path, pass, .. } => {
+            if let Some(id) = journal.jobs.get(path).cloned() {
+                journal.write(&format!("pass {} {}", id, pass), None);
+            }
+        }
+        Event::Renamed { from, to } => {
+            let key = journal.renamed.remove(from).unwrap_or_else(|| from.to_path_buf());
+            if let Some(id) = journal.jobs.get(&key).cloned() {
+                journal.write(&format!("rename {} ", id), to.file_name());
+                journal.renamed.insert(to.to_path_buf(), key);
+            }
+        }
+        Event::Removed { path } |
+        Event::Done { path, .. } => {
+            if let Some(id) = journal.jobs.remove(path) {
+                journal.write(&format!("done {}", id), None);
+                journal.renamed.retain(|_, key| key != path);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Records that the tree at `path` is about to be shredded. Returns the id to pass to `end` once
+/// it is gone, or `None` if the journal is not open.
+pub(crate) fn begin_tree(path: &Path, config: &Config) -> Option<String> {
+    let mut guard = JOURNAL.lock().unwrap();
+    let journal = guard.as_mut()?;
+    let id = journal.new_id();
+    let stat = Entry::of(path).ok().map(|entry| entry.stat);
+    journal.write(&format!("start {} tree {} {} ", id, scheme(config), identity(stat)),
+                  Some(absolute(path).as_os_str()));
+    Some(id)
+}
+
+/// Records that the job `id` has been finished.
+pub(crate) fn end(id: &str) {
+    if let Some(ref mut journal) = *JOURNAL.lock().unwrap() {
+        journal.write(&format!("done {}", id), None);
+    }
+}
+
+impl Journal {
+    /// An id that is unique across processes: time, pid and a counter.
+    fn new_id(&mut self) -> String {
+        let secs = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        self.count += 1;
+        format!("{}-{}-{}", secs, process::id(), self.count)
+    }
+
+    /// Appends `record`, followed by `field` escaped, and flushes it to disk. Failing to journal
+    /// does not stop the shredding.
+    fn write(&mut self, record: &str, field: Option<&OsStr>) {
+        let mut line = record.as_bytes().to_vec();
+        if let Some(field) = field {
+            escape(field, &mut line);
+        }
+        line.push(b'\n');
+
+        if let Err(e) = self.file.write_all(&line).and_then(|_| self.file.sync_data()) {
+            eprintln!("shrem: cannot write to the journal: {}", e);
+        }
+    }
+}
+
+/// The `DEV:INO:SIZE` field for an entry with `stat`, if it could be looked at.
+fn identity(stat: Option<Stat>) -> String {
+    match stat {
+        Some(stat) => {
+            let (dev, ino) = stat.id();
+            format!("{}:{}:{}", dev, ino, stat.size())
+        }
+        None => "-".to_owned(),
+    }
+}
+
+fn absolute(path: &Path) -> PathBuf {
+    env::current_dir().map(|dir| dir.join(path)).unwrap_or_else(|_| path.into())
+}
+
+/// How `config` overwrites files, in the form stored in `start` records.
+fn scheme(config: &Config) -> String {
+    match config.method {
+        Some(method) => format!("method:{}", method.name),
+        None => {
+            let n = config.iterations.unwrap_or(super::DEFAULT_ITERATIONS);
+            format!("random:{}{}", n, if config.zero { ":zero" } else { "" })
+        }
+    }
+}
+
+/// `config` with the passes set according to `scheme`.
+fn with_scheme(config: &Config, scheme: &str) -> Option<Config> {
+    let mut config = config.clone();
+    let mut parts = scheme.split(':');
+    match parts.next()? {
+        "method" => {
+            config.method = Some(method::find(parts.next()?)?);
+        }
+        "random" => {
+            config.method = None;
+            config.iterations = Some(parts.next()?.parse().ok()?);
+            config.zero = parts.next() == Some("zero");
+        }
+        _ => return None,
+    }
+    Some(config)
+}
+
+fn escape(s: &OsStr, out: &mut Vec<u8>) {
+    for &b in s.as_bytes() {
+        if b <= b' ' || b == b'%' || b == 0x7f {
+            out.extend_from_slice(format!("%{:02X}", b).as_bytes());
+        } else {
+            out.push(b);
+        }
+    }
+}
+
+fn unescape(s: &[u8]) -> PathBuf {
+    let mut out = Vec::with_capacity(s.len());
+    let mut i = 0;
+    while i < s.len() {
+        let hex = s.get(i + 1..i + 3)
+            .and_then(|h| std::str::from_utf8(h).ok())
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+        match hex {
+            Some(b) if s[i] == b'%' => {
+                out.push(b);
+                i += 3;
+            }
+            _ => {
+                out.push(s[i]);
+                i += 1;
+            }
+        }
+    }
+    PathBuf::from(OsStr::from_bytes(&out))
+}
+
+/// Finishes the unfinished jobs of the journal, which must have been opened with `open`. Files
+/// get the passes they are missing (with the native engine, since only it can start at a given
+/// pass) and are then renamed and removed; directories and other names of overwritten files are
+/// renamed and removed. Then what is
+/// left of interrupted trees is shredded like with `-r`.
+///
+/// Errors are reported for each job; `ShremError::Incomplete` is returned if any job failed.
+pub fn resume(path: &Path, config: &Config) -> Result<(), ShremError> {
+    let mut jobs = unfinished(path)?;
+    // Trees contain the other jobs, so they come last.
+    jobs.sort_by_key(|job| job.kind == JobKind::Tree);
+    let mut failed = 0;
+
+    for job in &jobs {
+        let result = finish(job, config);
+        if let Err(ShremError::Interrupted) = result {
+            return Err(ShremError::Interrupted);
+        }
+        if let Err(e) = result {
+            report_error(&job.current, &e, config);
+            failed += 1;
+            if !config.force {
+                break;
+            }
+        }
+    }
+
+    if failed > 0 {
+        Err(ShremError::Incomplete(failed))
+    } else {
+        Ok(())
+    }
+}
+
+/// Makes the events about the current path of `job` continue its records.
+fn adopt(job: &Job) {
+    if let Some(ref mut journal) = *JOURNAL.lock().unwrap() {
+        journal.jobs.insert(job.current.clone(), job.id.clone());
+    }
+}
+
+/// Finishes a single unfinished job. Errors are returned rather than reported.
+///
+/// The entry now at the job's path is only touched if it is the one the job was started on:
+/// the same inode on the same device, and for files the same size (or none, if all passes were
+/// done and the file was truncated before its removal). Otherwise, and for jobs whose entry the
+/// journal does not identify, `ShremError::Changed` is returned.
+pub fn finish(job: &Job, config: &Config) -> Result<(), ShremError> {
+    check_interrupt()?;
+
+    let path = job.current.as_path();
+
+    let entry = match Entry::of(path) {
+        Ok(entry) => entry,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+            // Gone already, e.g. removed just before the process died.
+            end(&job.id);
+            return Ok(());
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    let (dev, ino, size) = job.identity.ok_or_else(|| ShremError::Changed(path.to_path_buf()))?;
+    if entry.stat.id() != (dev, ino) {
+        return Err(ShremError::Changed(path.to_path_buf()));
+    }
+
+    match job.kind {
+        JobKind::Dir => {
+            adopt(job);
+            return remove_dir_at(&entry, config);
+        }
+        JobKind::Link => {
+            adopt(job);
+            return remove_link_at(&entry, config);
+        }
+        _ => {}
+    }
+
+    let mut config = with_scheme(config, &job.scheme)
+        .ok_or(ShremError::Unsupported("unknown scheme in the journal"))?;
+
+    if job.kind == JobKind::Tree {
+        config.recursive = true;
+        super::shred_tree(path, &config)?;
+        end(&job.id);
+        return Ok(());
+    }
+
+    let truncated = entry.stat.size() == 0 && job.passes >= method::passes(&config).len();
+    if entry.stat.size() != size && !truncated {
+        return Err(ShremError::Changed(path.to_path_buf()));
+    }
+
+    adopt(job);
+    // Only the native engine can start at a given pass.
+    config.backend = BackendKind::Native;
+    shred_file_at(&entry, &config, job.passes)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs::{self, OpenOptions};
+    use std::io::Write;
+    use std::os::unix::fs::MetadataExt;
+    use std::path::Path;
+    use std::process;
+
+    use super::{finish, parse, wipe, Job, JobKind};
+    use {Config, ShremError};
+
+    #[test]
+    fn finished_jobs_are_dropped() {
+        let jobs = parse(&b"start 1 file random:3:zero /a/b\n\
+                            start 2 dir - /a/c\n\
+                            pass 1 1\n\
+                            done 2\n"[..])
+            .unwrap();
+        assert_eq!(jobs.len(), 1);
+        assert_eq!(jobs[0].id, "1");
+        assert_eq!(jobs[0].kind, JobKind::File);
+        assert_eq!(jobs[0].scheme, "random:3:zero");
+        assert_eq!(jobs[0].passes, 1);
+        assert_eq!(jobs[0].identity, None);
+    }
+
+    #[test]
+    fn entries_are_identified() {
+        let jobs = parse(&b"start 1 file random:3 2049:1234:4096 /a/b\n\
+                            start 2 dir - - /a/c\n\
+                            start 3 file random:3 1:2 /a/d\n"[..])
+            .unwrap();
+        assert_eq!(jobs[0].identity, Some((2049, 1234, 4096)));
+        assert_eq!(jobs[0].original, Path::new("/a/b"));
+        assert_eq!(jobs[1].identity, None);
+        assert_eq!(jobs[1].original, Path::new("/a/c"));
+        assert_eq!(jobs[2].identity, None);
+    }
+
+    #[test]
+    fn other_entries_at_the_path_are_left_alone() {
+        let root = env::temp_dir().join(format!("shrem-test-journal-{}", process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir(&root).unwrap();
+        let path = root.join("0");
+        fs::write(&path, b"unrelated").unwrap();
+        let meta = fs::metadata(&path).unwrap();
+
+        let mut job = Job {
+            id: "1".to_owned(),
+            kind: JobKind::File,
+            scheme: "random:1".to_owned(),
+            original: root.join("secret"),
+            current: path.clone(),
+            passes: 0,
+            identity: Some((meta.dev(), meta.ino() + 1, meta.size())),
+        };
+        let config = Config::default();
+        for identity in &[None,
+                          job.identity,
+                          Some((meta.dev(), meta.ino(), meta.size() + 1))] {
+            job.identity = *identity;
+            match finish(&job, &config) {
+                Err(ShremError::Changed(ref p)) if *p == path => {}
+                result => panic!("{:?}", result),
+            }
+            assert_eq!(fs::read(&path).unwrap(), b"unrelated");
+        }
+
+        job.identity = Some((meta.dev(), meta.ino(), meta.size()));
+        finish(&job, &config).unwrap();
+        assert!(!path.exists());
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn other_names_are_unlinked() {
+        let root = env::temp_dir().join(format!("shrem-test-journal-link-{}", process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir(&root).unwrap();
+        fs::write(root.join("a"), b"overwritten").unwrap();
+        fs::hard_link(root.join("a"), root.join("b")).unwrap();
+        let meta = fs::metadata(root.join("b")).unwrap();
+
+        let record = format!("start 1 link - {}:{}:{} {}\n",
+                             meta.dev(),
+                             meta.ino(),
+                             meta.size(),
+                             root.join("b").display());
+        let jobs = parse(record.as_bytes()).unwrap();
+        assert_eq!(jobs[0].kind, JobKind::Link);
+
+        finish(&jobs[0], &Config::default()).unwrap();
+        assert!(!root.join("b").exists());
+        assert_eq!(fs::read(root.join("a")).unwrap(), b"overwritten");
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn renames_are_relative_to_the_directory() {
+        let jobs = parse(&b"start 1 file random:1 /a/my%20file\n\
+                            rename 1 0000\n\
+                            rename 1 /a/000\n\
+                            rename 1 00\n"[..])
+            .unwrap();
+        assert_eq!(jobs[0].original, Path::new("/a/my file"));
+        assert_eq!(jobs[0].current, Path::new("/a/00"));
+    }
+
+    #[test]
+    fn cut_off_last_line_is_ignored() {
+        let jobs = parse(&b"start 1 file random:3 /a/b\npass 1 1\npass 1 2\npass 1 3"[..]).unwrap();
+        assert_eq!(jobs[0].passes, 2);
+
+        let jobs = parse(&b"start 1 file random:3 /a/b\nrename 1 00"[..]).unwrap();
+        assert_eq!(jobs[0].current, Path::new("/a/b"));
+
+        let jobs = parse(&b"start 1 file random:3 /a/b\ndo"[..]).unwrap();
+        assert_eq!(jobs.len(), 1);
+
+        assert!(parse(&b"start 1 file random:3 /a/bc"[..]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn torn_lines_are_skipped() {
+        // A record cut off by a crash, with the next process appending right after it.
+        let jobs = parse(&b"start 1 file random:3 /a/b\n\
+                            pass 1 1pass 2 3\n\
+                            start 2 file random:3 /a/cstart 3 dir - /a/d\n\
+                            rename 1 0done 1\n\
+                            start\n\
+                            \n\
+                            bogus 1 2 3\n"[..])
+            .unwrap();
+        assert_eq!(jobs.len(), 1);
+        assert_eq!(jobs[0].id, "1");
+        assert_eq!(jobs[0].passes, 0);
+        assert_eq!(jobs[0].current, Path::new("/a/b"));
+    }
+
+    #[test]
+    fn wiping_empties_and_keeps_appending() {
+        let path = env::temp_dir().join(format!("shrem-test-journal-wipe-{}", process::id()));
+        let mut file = OpenOptions::new().read(true).append(true).create(true).open(&path).unwrap();
+        file.write_all(&[b'x'; 20000]).unwrap();
+
+        wipe(&mut file).unwrap();
+        assert_eq!(file.metadata().unwrap().len(), 0);
+        file.write_all(b"done 1\n").unwrap();
+        file.write_all(b"done 2\n").unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"done 1\ndone 2\n");
+        fs::remove_file(&path).unwrap();
+    }
+}
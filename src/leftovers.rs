@@ -0,0 +1,110 @@
+//! Finding entries left behind by interrupted runs, for `shrem --scan-leftovers`.
+//!
+//! An interrupted rename chain leaves a name made by
+//! [`generate_new_path`](../fn.generate_new_path.html): characters from `0-9a-zA-Z_`, tried in
+//! order from `0…0`, so all but the last character are `0` unless dozens of such names were taken.
+//! Only names of that form are taken for leftovers, which misses the rare ones further along the
+//! search, rather than catch ordinary short names. Entries the journal knows about are found by
+//! their path instead, whatever their name; this is the only way to find the random names of
+//! `NameWipe::Random`.
+
+use std::fs;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+use walkdir::WalkDir;
+
+use super::{shred_dir, shred_file, Config, ShremError};
+use journal::{self, Job, JobKind};
+
+/// Something that looks like it was left behind.
+#[derive(Debug)]
+pub struct Leftover {
+    pub path: PathBuf,
+    pub dir: bool,
+    /// The unfinished journal job for this entry, if there is one.
+    pub job: Option<Job>,
+}
+
+/// Whether `name` could have been generated by `generate_new_path` early in its search: `0`s,
+/// followed by at most one other character of its alphabet.
+pub fn looks_obfuscated(name: &[u8]) -> bool {
+    match name.split_last() {
+        Some((&last, zeros)) => {
+            zeros.iter().all(|&b| b == b'0') && (last == b'0' || !zeros.is_empty()) &&
+            (last.is_ascii_alphanumeric() || last == b'_')
+        }
+        None => false,
+    }
+}
+
+/// Walks `dir` for entries whose names look obfuscated, and for entries of the unfinished `jobs`.
+/// Files come first, then directories from the deepest, which is the order to remove them in.
+pub fn scan(dir: &Path, jobs: &[Job]) -> io::Result<Vec<Leftover>> {
+    let dir = fs::canonicalize(dir)?;
+    let mut leftovers = Vec::new();
+
+    for entry in WalkDir::new(&dir).min_depth(1) {
+        let entry = entry?;
+        let job = jobs.iter()
+            .find(|job| job.kind != JobKind::Tree && job.current == entry.path())
+            .cloned();
+        let name = entry.file_name().as_bytes();
+        let file_type = entry.file_type();
+
+        // Only regular files and directories are ever renamed.
+        if !file_type.is_file() && !file_type.is_dir() {
+            continue;
+        }
+
+        if job.is_some() || looks_obfuscated(name) {
+            leftovers.push(Leftover {
+                path: entry.path().to_path_buf(),
+                dir: file_type.is_dir(),
+                job,
+            });
+        }
+    }
+
+    leftovers.sort_by_key(|l| (l.dir, usize::MAX - l.path.components().count()));
+    Ok(leftovers)
+}
+
+/// Finishes shredding `leftover`: its journal job is resumed, or else a file is shredded with
+/// `config` and an empty directory is removed. Directories that are not empty are not touched,
+/// since they are unlikely to be shrem's.
+pub fn finish(leftover: &Leftover, config: &Config) -> Result<(), ShremError> {
+    if let Some(ref job) = leftover.job {
+        return journal::finish(job, config);
+    }
+
+    if leftover.dir {
+        if fs::read_dir(&leftover.path)?.next().is_some() {
+            return Err(io::Error::from_raw_os_error(libc::ENOTEMPTY).into());
+        }
+        shred_dir(&leftover.path, config)
+    } else {
+        shred_file(&leftover.path, config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::looks_obfuscated;
+
+    #[test]
+    fn generated_names() {
+        for name in &["0", "00", "0000000", "01", "0a", "000Z", "00_"] {
+            assert!(looks_obfuscated(name.as_bytes()), "{}", name);
+        }
+    }
+
+    #[test]
+    fn other_names() {
+        let names = ["", "a", "1", "_", "ab", "go", "db", "10", "a0", "00ab", "0-", "0.0", "000 "];
+        for name in &names {
+            assert!(!looks_obfuscated(name.as_bytes()), "{}", name);
+        }
+    }
+}
@@ -0,0 +1,1292 @@
+//! Overwrite files repeatedly and then remove them: `shred` with the options of `rm`.
+//!
+//! [`Shredder`](struct.Shredder.html) is the entry point for library users:
+//!
+//! ```no_run
+//! use shrem::Shredder;
+//! use shrem::event::Event;
+//!
+//! Shredder::new()
+//!     .passes(3)
+//!     .verify(true)
+//!     .on_event(|event| if let Event::PassDone { path, pass, .. } = *event {
+//!         println!("{}: pass {} done", path.display(), pass);
+//!     })
+//!     .shred("secret.txt")
+//!     .unwrap();
+//! ```
+
+extern crate libc;
+extern crate walkdir;
+
+use std::error::Error;
+use std::ffi::{OsStr, OsString};
+use std::fmt::{Display, Formatter};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::Write;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::process::ExitStatus;
+use std::result::Result;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+use at::{Dir, Entry};
+use backend::{BackendKind, Target};
+use event::{Event, Observer, OutputFormat};
+use extents::Extent;
+use forensic::Samples;
+use mounts::Layers;
+use method::Method;
+use plan::{Plan, Step};
+
+mod at;
+pub mod backend;
+pub mod event;
+mod extents;
+mod forensic;
+pub mod journal;
+pub mod leftovers;
+pub mod method;
+mod mounts;
+mod native;
+mod parallel;
+mod plan;
+pub mod report;
+mod sha256;
+
+/// Number of random passes if neither `iterations` nor a method is given.
+pub const DEFAULT_ITERATIONS: usize = 3;
+/// Write speed assumed for dry-run estimates, in MB/s.
+pub const DEFAULT_THROUGHPUT: f64 = 100.0;
+/// Number of renames with `NameWipe::Random` if no other number is given.
+pub const DEFAULT_RENAME_ROUNDS: usize = 3;
+
+/// Everything that controls how files are shredded.
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Descend into directories.
+    pub recursive: bool,
+    /// Keep going after errors.
+    pub force: bool,
+    /// Print what is being done (with `OutputFormat::Text`).
+    pub verbose: bool,
+    /// Ask on stderr before removing each entry.
+    pub interactive: bool,
+    /// Refuse to operate on `/` recursively.
+    pub preserve_root: bool,
+    /// Only overwrite; don't rename and unlink.
+    pub no_remove: bool,
+    /// Add a final pass of zeros (ignored if `method` is set).
+    pub zero: bool,
+    pub backend: BackendKind,
+    /// Number of random passes (ignored if `method` is set).
+    pub iterations: Option<usize>,
+    pub method: Option<&'static Method>,
+    /// Read files back after the last pass.
+    pub verify: bool,
+    pub output: OutputFormat,
+    /// Collect records for a certificate of destruction (see the `report` module).
+    pub report: bool,
+    /// Include the SHA-256 of the original contents in the report.
+    pub report_hash: bool,
+    /// Only report what would be done.
+    pub dry_run: bool,
+    /// Assumed write speed in bytes per second, for the `--dry-run` estimate.
+    pub throughput: f64,
+    /// Called for every event, in addition to the printed output.
+    pub observer: Option<Observer>,
+    /// Number of files shredded at the same time by [`shred_all`](fn.shred_all.html).
+    pub jobs: usize,
+    /// How names are obfuscated before removal.
+    pub name_wipe: NameWipe,
+    /// Which symlinks are followed.
+    pub symlinks: Symlinks,
+    /// Overwrite the regular files that symlinks which are not followed point to, without
+    /// removing them; only the links are removed.
+    pub shred_link_targets: bool,
+    /// What to do with regular files that have other hard links.
+    pub hard_links: HardLinks,
+    /// What to do when overwriting a file would not reach the blocks its data is in.
+    pub on_cow: OnCow,
+    /// Check after each pass that the data was written to the blocks it was in (native engine).
+    pub check_extents: bool,
+    /// Check on the block device that samples of the original data are gone after shredding.
+    pub forensic_verify: bool,
+    /// What to do with files on filesystems where overwriting is not reliable.
+    pub on_weak_fs: OnWeakFs,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            recursive: false,
+            force: false,
+            verbose: false,
+            interactive: false,
+            preserve_root: true,
+            no_remove: false,
+            zero: true,
+            backend: BackendKind::Native,
+            iterations: None,
+            method: None,
+            verify: false,
+            output: OutputFormat::None,
+            report: false,
+            report_hash: false,
+            dry_run: false,
+            throughput: DEFAULT_THROUGHPUT * 1_000_000.0,
+            observer: None,
+            jobs: 1,
+            name_wipe: NameWipe::Shorten,
+            symlinks: Symlinks::Never,
+            shred_link_targets: false,
+            hard_links: HardLinks::ShredAll,
+            on_cow: OnCow::Warn,
+            check_extents: false,
+            forensic_verify: false,
+            on_weak_fs: OnWeakFs::Warn,
+        }
+    }
+}
+
+/// Which symlinks are followed. A followed symlink is treated like what it points to, which is
+/// shredded and removed, and is then removed itself. Symlinks that are not followed are removed
+/// without touching what they point to.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Symlinks {
+    /// None of them (`-P`). The default.
+    Never,
+    /// Those given as arguments (`-H`).
+    CommandLine,
+    /// All of them, also in directory trees (`-L`).
+    All,
+}
+
+/// How the name of a file or directory is hidden before it is removed. The parent directory is
+/// synced after every rename either way, so that the renames reach the disk.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum NameWipe {
+    /// Successively shorter names, `0…0` first, like `shred -u`.
+    Shorten,
+    /// The given number of random names as long as the original one.
+    Random(usize),
+}
+
+impl NameWipe {
+    /// Parses `--wipe-names`; `rounds` is only used by `random`.
+    pub fn from_name(name: &str, rounds: usize) -> Option<NameWipe> {
+        match name {
+            "shorten" => Some(NameWipe::Shorten),
+            "random" => Some(NameWipe::Random(rounds)),
+            _ => None,
+        }
+    }
+}
+
+/// What to do with a regular file that has other hard links. Overwriting it changes the data
+/// seen through all of them, while removing it leaves the data reachable through the others.
+///
+/// Each file is overwritten once per argument: when a tree contains more than one of its names,
+/// those found later are only removed (`ShredAll`) or left alone.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum HardLinks {
+    /// Warn and ask whether to shred it anyway; it is skipped if not. The default of the command
+    /// line.
+    Ask,
+    /// Overwrite and remove it like any other file (`shred-all`). The default of `Config`.
+    ShredAll,
+    /// Leave it alone (`skip`).
+    Skip,
+    /// Overwrite it, but keep it (`overwrite-only`).
+    OverwriteOnly,
+}
+
+impl HardLinks {
+    /// Parses `--hardlinks`.
+    pub fn from_name(name: &str) -> Option<HardLinks> {
+        match name {
+            "shred-all" => Some(HardLinks::ShredAll),
+            "skip" => Some(HardLinks::Skip),
+            "overwrite-only" => Some(HardLinks::OverwriteOnly),
+            _ => None,
+        }
+    }
+}
+
+/// What to do when overwriting a file would write new blocks and leave the old ones, and so the
+/// data, in place: on copy-on-write filesystems (btrfs, ZFS, bcachefs), and for files that share
+/// extents with reflinked copies or snapshots.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum OnCow {
+    /// Shred the file, with a warning. For the filesystem, the warning is given once. The default.
+    Warn,
+    /// Don't touch the file and fail with `ShremError::CopyOnWrite`.
+    Refuse,
+    /// Shred the file without a warning.
+    Proceed,
+}
+
+impl OnCow {
+    /// Parses `--on-cow`.
+    pub fn from_name(name: &str) -> Option<OnCow> {
+        match name {
+            "warn" => Some(OnCow::Warn),
+            "refuse" => Some(OnCow::Refuse),
+            "proceed" => Some(OnCow::Proceed),
+            _ => None,
+        }
+    }
+}
+
+/// What to do with files on filesystems where overwriting may leave the data behind: ext3/ext4
+/// with `data=journal`, the log-structured f2fs and nilfs2, tmpfs, network filesystems, FUSE and
+/// overlayfs. Why is explained once per mount in any case.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum OnWeakFs {
+    /// Shred them anyway. The default.
+    Warn,
+    /// Leave them alone.
+    Skip,
+    /// Don't touch them and fail with `ShremError::WeakFilesystem`.
+    Refuse,
+}
+
+impl OnWeakFs {
+    /// Parses `--on-weak-fs`.
+    pub fn from_name(name: &str) -> Option<OnWeakFs> {
+        match name {
+            "warn" => Some(OnWeakFs::Warn),
+            "skip" => Some(OnWeakFs::Skip),
+            "refuse" => Some(OnWeakFs::Refuse),
+            _ => None,
+        }
+    }
+}
+
+/// Builder for shredding files from library code. Nothing is printed; progress is available
+/// through [`on_event`](#method.on_event).
+#[derive(Debug, Clone, Default)]
+pub struct Shredder {
+    config: Config,
+}
+
+impl Shredder {
+    /// Three random passes and a zero pass with the native engine, then rename and unlink.
+    pub fn new() -> Shredder {
+        Shredder::default()
+    }
+
+    /// Creates a shredder from a complete configuration.
+    pub fn with_config(config: Config) -> Shredder {
+        Shredder { config }
+    }
+
+    /// Number of random passes. Clears any method set before.
+    pub fn passes(mut self, n: usize) -> Shredder {
+        self.config.iterations = Some(n);
+        self.config.method = None;
+        self
+    }
+
+    /// Overwrites with the passes of a standard scheme, e.g. `method::find("dod")`.
+    pub fn method(mut self, method: &'static Method) -> Shredder {
+        self.config.method = Some(method);
+        self
+    }
+
+    /// Whether to add a final pass of zeros. Defaults to `true`.
+    pub fn zero(mut self, yes: bool) -> Shredder {
+        self.config.zero = yes;
+        self
+    }
+
+    /// Whether to read files back after the last pass. Defaults to `false`.
+    pub fn verify(mut self, yes: bool) -> Shredder {
+        self.config.verify = yes;
+        self
+    }
+
+    /// Whether to shred directories and their contents. Defaults to `false`.
+    pub fn recursive(mut self, yes: bool) -> Shredder {
+        self.config.recursive = yes;
+        self
+    }
+
+    /// Whether to keep going after an entry of a directory tree fails. Defaults to `false`.
+    pub fn force(mut self, yes: bool) -> Shredder {
+        self.config.force = yes;
+        self
+    }
+
+    /// Whether to rename and unlink files after overwriting them. Defaults to `true`.
+    pub fn remove(mut self, yes: bool) -> Shredder {
+        self.config.no_remove = !yes;
+        self
+    }
+
+    pub fn backend(mut self, backend: BackendKind) -> Shredder {
+        self.config.backend = backend;
+        self
+    }
+
+    /// Calls `f` for every event: passes started and finished, renames, removals, errors.
+    pub fn on_event<F>(mut self, f: F) -> Shredder
+        where F: Fn(&Event) + Send + Sync + 'static
+    {
+        self.config.observer = Some(Observer(Arc::new(f)));
+        self
+    }
+
+    /// Number of files [`shred_all`](#method.shred_all) works on at the same time. Defaults to 1.
+    pub fn jobs(mut self, n: usize) -> Shredder {
+        self.config.jobs = n.max(1);
+        self
+    }
+
+    /// How names are obfuscated before removal. Defaults to `NameWipe::Shorten`.
+    pub fn name_wipe(mut self, name_wipe: NameWipe) -> Shredder {
+        self.config.name_wipe = name_wipe;
+        self
+    }
+
+    /// Which symlinks are followed. Defaults to `Symlinks::Never`.
+    pub fn symlinks(mut self, symlinks: Symlinks) -> Shredder {
+        self.config.symlinks = symlinks;
+        self
+    }
+
+    /// Whether to overwrite, but keep, the files that symlinks which are not followed point to.
+    /// Defaults to `false`.
+    pub fn shred_link_targets(mut self, yes: bool) -> Shredder {
+        self.config.shred_link_targets = yes;
+        self
+    }
+
+    /// What to do with regular files that have other hard links. Defaults to
+    /// `HardLinks::ShredAll`.
+    pub fn hard_links(mut self, hard_links: HardLinks) -> Shredder {
+        self.config.hard_links = hard_links;
+        self
+    }
+
+    /// What to do when overwriting would leave the old data in place on the device. Defaults to
+    /// `OnCow::Warn`.
+    pub fn on_cow(mut self, on_cow: OnCow) -> Shredder {
+        self.config.on_cow = on_cow;
+        self
+    }
+
+    /// Whether to check after each pass that the filesystem overwrote the data in place, by
+    /// comparing the extents of the file. Needs the native engine. Defaults to `false`.
+    pub fn check_extents(mut self, yes: bool) -> Shredder {
+        self.config.check_extents = yes;
+        self
+    }
+
+    /// Whether to read samples of each file before it is overwritten and check afterwards that
+    /// none of them is left on the block device. Needs read access to the device. Defaults to
+    /// `false`.
+    pub fn forensic_verify(mut self, yes: bool) -> Shredder {
+        self.config.forensic_verify = yes;
+        self
+    }
+
+    /// What to do with files on filesystems where overwriting is not reliable. Defaults to
+    /// `OnWeakFs::Warn`.
+    pub fn on_weak_fs(mut self, on_weak_fs: OnWeakFs) -> Shredder {
+        self.config.on_weak_fs = on_weak_fs;
+        self
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Shreds a file, or a directory tree if `recursive` is set.
+    pub fn shred<P: AsRef<Path>>(&self, path: P) -> Result<(), ShremError> {
+        shred(path, &self.config)
+    }
+
+    /// Shreds several files or directory trees, using `jobs` threads.
+    pub fn shred_all<P: AsRef<Path>>(&self, paths: &[P]) -> Result<(), ShremError> {
+        let paths = paths.iter().map(AsRef::as_ref).collect::<Vec<_>>();
+        shred_all(&paths, &self.config)
+    }
+}
+
+/// Why a file or directory could not be shredded.
+#[derive(Debug)]
+pub enum ShremError {
+    IoError(io::Error),
+    PreservedRootError,
+    ExternalProcessError(ExitStatus),
+    NotFound(PathBuf),
+    IsADirectory(PathBuf),
+    /// Neither a regular file nor a directory, e.g. a symlink, which is not followed.
+    NotARegularFile(PathBuf),
+    /// The file was replaced by another one between being found and being opened. Nothing was
+    /// written to either.
+    Replaced(PathBuf),
+    /// The entry at this path is not the one the journal says was being shredded there, or the
+    /// journal does not say which one that was. It was left alone.
+    Changed(PathBuf),
+    /// This symlink leads to a directory that contains it, so it was not followed.
+    Loop(PathBuf),
+    /// The file is on a copy-on-write filesystem or shares extents with other files, and
+    /// `OnCow::Refuse` is set. Nothing was written to it.
+    CopyOnWrite(PathBuf),
+    /// The file is on a filesystem where overwriting is not reliable, and `OnWeakFs::Refuse` is
+    /// set. Nothing was written to it.
+    WeakFilesystem(PathBuf),
+    /// The data of the file is in a read-only lower layer of an overlay, at this path, where it
+    /// stays. If the file had been copied up, its copy in the upper layer was shredded.
+    LowerLayer(PathBuf),
+    BackendUnavailable(&'static str),
+    Unsupported(&'static str),
+    /// Reading the file back after the last pass found different data at this offset.
+    VerificationFailed(u64),
+    /// `--forensic-verify` found original data on the block device at this offset.
+    DataRemains(u64),
+    /// Some entries of a directory tree could not be removed. Each of them has been reported with
+    /// an [`Event::Error`](event/enum.Event.html).
+    Incomplete(usize),
+    /// [`interrupt`](fn.interrupt.html) was called. The file being overwritten at the time was
+    /// left partially overwritten.
+    Interrupted,
+}
+
+impl Display for ShremError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match *self {
+            ShremError::IoError(ref e) => e.fmt(f),
+            ShremError::PreservedRootError => {
+                f.write_str("It is dangerous to operate on '/' recursively. \
+                             Use --no-preserve-root to override this failsafe.")
+            }
+            ShremError::ExternalProcessError(ref status) => {
+                write!(f, "External process exited with an error ({}).", status)
+            }
+            ShremError::NotFound(_) => f.write_str("No such file or directory"),
+            ShremError::IsADirectory(_) => f.write_str("Is a directory"),
+            ShremError::NotARegularFile(_) => f.write_str("Not a regular file"),
+            ShremError::Replaced(_) => f.write_str("Replaced by another file before it was opened"),
+            ShremError::Changed(_) => {
+                f.write_str("Not the entry that the journal recorded here, so it was left alone")
+            }
+            ShremError::Loop(_) => f.write_str("Symlink loop: it leads to a directory containing it"),
+            ShremError::CopyOnWrite(_) => {
+                f.write_str("Copy-on-write storage: overwriting would leave the old data in place")
+            }
+            ShremError::WeakFilesystem(_) => {
+                f.write_str("Overwriting is not reliable on this filesystem")
+            }
+            ShremError::LowerLayer(ref lower) => {
+                write!(f, "The data stays in a lower layer of the overlay, at '{}'", lower.display())
+            }
+            ShremError::BackendUnavailable(name) => {
+                write!(f, "Backend '{}' is not installed.", name)
+            }
+            ShremError::Unsupported(what) => write!(f, "Unsupported option: {}.", what),
+            ShremError::VerificationFailed(offset) => {
+                write!(f, "Verification failed: unexpected data at offset {}.", offset)
+            }
+            ShremError::DataRemains(offset) => {
+                write!(f,
+                       "Forensic verification failed: original data found on the device at \
+                        byte {}.",
+                       offset)
+            }
+            ShremError::Incomplete(n) => write!(f, "{} entries could not be removed.", n),
+            ShremError::Interrupted => f.write_str("Interrupted"),
+        }
+    }
+}
+
+impl ShremError {
+    /// Name of the variant, for machine-readable output.
+    pub fn kind(&self) -> &'static str {
+        match *self {
+            ShremError::IoError(_) => "IoError",
+            ShremError::PreservedRootError => "PreservedRootError",
+            ShremError::ExternalProcessError(_) => "ExternalProcessError",
+            ShremError::NotFound(_) => "NotFound",
+            ShremError::IsADirectory(_) => "IsADirectory",
+            ShremError::NotARegularFile(_) => "NotARegularFile",
+            ShremError::Replaced(_) => "Replaced",
+            ShremError::Changed(_) => "Changed",
+            ShremError::Loop(_) => "Loop",
+            ShremError::CopyOnWrite(_) => "CopyOnWrite",
+            ShremError::WeakFilesystem(_) => "WeakFilesystem",
+            ShremError::LowerLayer(_) => "LowerLayer",
+            ShremError::BackendUnavailable(_) => "BackendUnavailable",
+            ShremError::Unsupported(_) => "Unsupported",
+            ShremError::VerificationFailed(_) => "VerificationFailed",
+            ShremError::DataRemains(_) => "DataRemains",
+            ShremError::Incomplete(_) => "Incomplete",
+            ShremError::Interrupted => "Interrupted",
+        }
+    }
+}
+
+impl Error for ShremError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        if let ShremError::IoError(ref e) = *self {
+            Some(e)
+        } else {
+            None
+        }
+    }
+}
+
+impl From<io::Error> for ShremError {
+    fn from(e: io::Error) -> ShremError {
+        ShremError::IoError(e)
+    }
+}
+
+impl From<walkdir::Error> for ShremError {
+    fn from(e: walkdir::Error) -> ShremError {
+        ShremError::IoError(e.into())
+    }
+}
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Asks all shredding in progress to stop: the current pass is abandoned at the next block, any
+/// backend process is killed, and no further files are started. Functions return
+/// `ShremError::Interrupted` from then on, until [`clear_interrupt`](fn.clear_interrupt.html) is
+/// called. Only an atomic store, so it may be called from a signal handler.
+pub fn interrupt() {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+}
+
+/// Undoes [`interrupt`](fn.interrupt.html), so that shredding can be started again. Meant for
+/// programs that keep running after an interrupted run; call it once that run has returned.
+pub fn clear_interrupt() {
+    INTERRUPTED.store(false, Ordering::SeqCst);
+}
+
+/// Whether [`interrupt`](fn.interrupt.html) has been called.
+pub fn is_interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+pub(crate) fn check_interrupt() -> Result<(), ShremError> {
+    if is_interrupted() {
+        Err(ShremError::Interrupted)
+    } else {
+        Ok(())
+    }
+}
+
+/// Reports `e` as an error event. Interruptions are not errors of the entry and are left to the
+/// caller.
+pub(crate) fn report_error(path: &Path, e: &ShremError, config: &Config) {
+    if let ShremError::Interrupted = *e {
+        return;
+    }
+    event::emit(config, Event::Error { path, error: e });
+}
+
+/// Shreds `path`: a regular file, or a whole directory tree if `config.recursive` is set. Other
+/// kinds of files are only removed, and symlinks are followed as `config.symlinks` says.
+///
+/// Errors are also reported as [`Event::Error`](event/enum.Event.html).
+pub fn shred<P: AsRef<Path>>(path: P, config: &Config) -> Result<(), ShremError> {
+    let path = path.as_ref();
+
+    let result = run_plan(Plan::new(path, config), config);
+
+    match result {
+        // The entries have already been reported one by one.
+        Err(ShremError::Incomplete(_)) | Ok(()) => {}
+        Err(ref e) => report_error(path, e, config),
+    }
+
+    result
+}
+
+/// Whether `path` is shredded as a directory tree: `config.recursive` is set and it is a
+/// directory, not a symlink to one.
+pub(crate) fn is_tree(path: &Path, config: &Config) -> bool {
+    config.recursive && fs::symlink_metadata(path).is_ok_and(|m| m.is_dir())
+}
+
+/// Carries out the steps of `plan`. Errors of a single entry are returned as they are; those of a
+/// tree are reported as they happen, and `ShremError::Incomplete` is returned. Unless
+/// `config.force` is set, stops at the first of them.
+fn run_plan(mut plan: Plan, config: &Config) -> Result<(), ShremError> {
+    if plan.is_single() {
+        return plan.next().map_or(Ok(()), |step| run_step(step, config));
+    }
+
+    let mut failed = 0;
+    for step in plan {
+        let path = step.path().to_path_buf();
+        match run_step(step, config) {
+            Ok(()) => {}
+            Err(ShremError::Interrupted) => return Err(ShremError::Interrupted),
+            Err(e) => {
+                report_error(&path, &e, config);
+                failed += 1;
+                if !config.force {
+                    break;
+                }
+            }
+        }
+    }
+
+    if failed > 0 {
+        Err(ShremError::Incomplete(failed))
+    } else {
+        Ok(())
+    }
+}
+
+pub(crate) fn run_step(step: Step, config: &Config) -> Result<(), ShremError> {
+    match step {
+        Step::Shred(ref entry) => shred_file_at(entry, config, 0),
+        Step::Overwrite(ref entry) => {
+            let config = Config { no_remove: true, ..config.clone() };
+            shred_file_at(entry, &config, 0)
+        }
+        Step::Remove(ref entry) => remove_special_at(entry, config),
+        Step::Unlink(ref entry) => remove_link_at(entry, config),
+        Step::Skip(ref entry) => {
+            event::emit(config, Event::Skipped { path: &entry.path });
+            Ok(())
+        }
+        // Everything below has been removed by now, unless something failed.
+        Step::RemoveDir(ref entry) => check_interrupt().and_then(|()| shred_dir_at(entry, config)),
+        Step::Failed(_, e) => Err(e),
+    }
+}
+
+/// Turns `ENOENT` into `ShremError::NotFound`.
+fn not_found(e: io::Error, path: &Path) -> ShremError {
+    if e.kind() == io::ErrorKind::NotFound {
+        ShremError::NotFound(path.to_path_buf())
+    } else {
+        e.into()
+    }
+}
+
+/// Shreds each of `paths` like [`shred`](fn.shred.html). Unless `config.force` is set, stops at
+/// the first one that fails.
+///
+/// With `config.jobs` above 1, files are shredded on that many threads, scheduled so that the
+/// threads are spread over the devices the files are on. Output is still printed in order.
+/// `config.interactive` always works on one file at a time, so that prompts are not interleaved.
+pub fn shred_all(paths: &[&Path], config: &Config) -> Result<(), ShremError> {
+    // Trees given as arguments are journaled so that `--resume` can finish what is left of them.
+    let trees = paths.iter()
+        .map(|path| if !config.dry_run && is_tree(path, config) {
+            journal::begin_tree(path, config)
+        } else {
+            None
+        })
+        .collect::<Vec<_>>();
+
+    if config.jobs > 1 && !config.interactive {
+        parallel::shred_all(paths, config)?;
+        for id in trees.iter().flatten() {
+            journal::end(id);
+        }
+        return Ok(());
+    }
+
+    let mut failed = 0;
+    for (path, tree) in paths.iter().zip(&trees) {
+        match shred(path, config) {
+            Err(ShremError::Interrupted) => return Err(ShremError::Interrupted),
+            Err(_) => {
+                failed += 1;
+                if !config.force {
+                    break;
+                }
+            }
+            Ok(()) => {
+                if let Some(ref id) = *tree {
+                    journal::end(id);
+                }
+            }
+        }
+    }
+
+    if failed > 0 {
+        Err(ShremError::Incomplete(failed))
+    } else {
+        Ok(())
+    }
+}
+
+/// The regular files that [`shred_all`](fn.shred_all.html) would overwrite, with their sizes,
+/// found by walking `paths` the same way. Entries that cannot be read are left out, and so are
+/// the other names of files with hard links. Nothing is asked: with `HardLinks::Ask`, they are
+/// all counted.
+pub fn regular_files(paths: &[&Path], config: &Config) -> Vec<(PathBuf, u64)> {
+    let mut files = Vec::new();
+    for path in paths {
+        files.extend(Plan::quiet(path, config).filter_map(|step| match step {
+            Step::Shred(entry) |
+            Step::Overwrite(entry) if entry.stat.is_file() => Some((entry.path, entry.stat.size())),
+            _ => None,
+        }));
+    }
+    files
+}
+
+/// Total size of the [`regular_files`](fn.regular_files.html) in `paths`.
+pub fn total_bytes(paths: &[&Path], config: &Config) -> u64 {
+    regular_files(paths, config).iter().map(|&(_, len)| len).sum()
+}
+
+/// Overwrites and removes a single regular file with the configured backend.
+pub fn shred_file<P: AsRef<Path>>(path: P, config: &Config) -> Result<(), ShremError> {
+    let path = path.as_ref();
+
+    check_interrupt()?;
+
+    let entry = Entry::of(path).map_err(|e| not_found(e, path))?;
+    shred_file_at(&entry, config, 0)
+}
+
+/// Shreds `entry`, which must be a regular file, like `shred_file`. The first `done` passes are
+/// skipped, which needs the native engine; this is how interrupted jobs are resumed.
+///
+/// The file is opened without following symlinks and only written to if it is the file that
+/// `entry.stat` describes. With `config.report`, files that are skipped or fail before anything
+/// is written are recorded in the report too.
+pub(crate) fn shred_file_at(entry: &Entry, config: &Config, done: usize) -> Result<(), ShremError> {
+    let path = entry.path.as_path();
+
+    let Prepared { file, storage, samples, before } = match prepare(entry, config) {
+        Ok(Some(prepared)) => prepared,
+        Ok(None) => {
+            if config.report {
+                report::not_shredded(entry, None);
+            }
+            return Ok(());
+        }
+        Err(e) => {
+            if config.report {
+                report::not_shredded(entry, Some(&e));
+            }
+            return Err(e);
+        }
+    };
+
+    let start = Instant::now();
+    let bytes = entry.stat.size();
+    event::emit(config, Event::Start { path, bytes });
+
+    let target = Target {
+        path,
+        file: &file,
+        dir: &entry.dir,
+        name: &entry.name,
+    };
+    let backend = config.backend.backend();
+    // The native engine is called directly, since only it can resume and check extents.
+    let result = if done > 0 || config.backend == BackendKind::Native {
+        native::overwrite_from(path, &file, config, done)
+    } else {
+        backend.overwrite(&target, config).map(|()| None)
+    }
+    .and_then(|placement| if config.no_remove || backend.removes() {
+        Ok(placement)
+    } else {
+        remove_file_at(entry, file, config).map(|()| placement)
+    })
+    .and_then(|placement| match samples {
+        Some(ref samples) => samples.check(path, config).map(|()| placement),
+        None => Ok(placement),
+    })
+    .and_then(|placement| match storage {
+        Storage::Overlay(Layers { lower: Some(lower), .. }) => Err(ShremError::LowerLayer(lower)),
+        _ => Ok(placement),
+    });
+    if let Some(before) = before {
+        report::record(before, &result, config);
+    }
+    result?;
+
+    event::emit(config, Event::Done { path, bytes, duration: start.elapsed() });
+
+    Ok(())
+}
+
+/// What `shred_file_at` finds out and opens before it writes anything.
+struct Prepared {
+    file: File,
+    storage: Storage,
+    samples: Option<Samples>,
+    before: Option<report::Before>,
+}
+
+/// Checks that `entry` is to be shredded and opens it, as far as `shred_file_at` goes before
+/// writing anything. `None` if the file is skipped, or only shown with `config.dry_run`.
+fn prepare(entry: &Entry, config: &Config) -> Result<Option<Prepared>, ShremError> {
+    let path = entry.path.as_path();
+
+    check_interrupt()?;
+
+    if entry.stat.is_dir() {
+        return Err(ShremError::IsADirectory(path.to_path_buf()));
+    }
+
+    if !entry.stat.is_file() {
+        return Err(ShremError::NotARegularFile(path.to_path_buf()));
+    }
+
+    if !check_filesystem(entry, config)? {
+        event::emit(config, Event::Skipped { path });
+        return Ok(None);
+    }
+    let storage = check_overlay(entry, config)?;
+
+    if config.interactive && !prompt(format_args!("remove file '{}'?", path.display()))? {
+        event::emit(config, Event::Skipped { path });
+        return Ok(None);
+    }
+
+    if config.dry_run {
+        event::emit(config, Event::WouldShred { path, bytes: entry.stat.size() });
+        return Ok(None);
+    }
+
+    let file = open_checked(entry, config.verify || config.report_hash || config.forensic_verify)?;
+    check_cow(entry, &file, config)?;
+    let samples = if config.forensic_verify {
+        Some(Samples::take(&file, entry.stat.dev())?)
+    } else {
+        None
+    };
+
+    let before = if config.report {
+        let backing = match storage {
+            Storage::Overlay(ref layers) => layers.upper.as_ref(),
+            _ => None,
+        };
+        let layer_unknown = storage == Storage::UnknownLayer;
+        Some(report::before(path, &file, backing, layer_unknown, config)?)
+    } else {
+        None
+    };
+
+    Ok(Some(Prepared {
+        file,
+        storage,
+        samples,
+        before,
+    }))
+}
+
+/// Opens the regular file `entry` for writing (and reading, with `read`), checking that it is
+/// still the file that was found.
+fn open_checked(entry: &Entry, read: bool) -> Result<File, ShremError> {
+    let file = entry.dir.open_file(&entry.name, read)?;
+    if !entry.stat.is_same(&file)? {
+        return Err(ShremError::Replaced(entry.path.clone()));
+    }
+    Ok(file)
+}
+
+/// Explains, once per mount, why overwriting may not destroy data on the filesystem `entry` is on,
+/// if there is a reason, and returns whether to go on as `config.on_weak_fs` says.
+fn check_filesystem(entry: &Entry, config: &Config) -> Result<bool, ShremError> {
+    let mount = match mounts::mount_of(&entry.dir, Path::new(&entry.name)) {
+        Some((mount, _)) => mount,
+        None => return Ok(true),
+    };
+    let caveat = match mount.caveat() {
+        Some(caveat) => caveat,
+        None => return Ok(true),
+    };
+
+    if mounts::first_on(mount) {
+        event::emit(config,
+                    Event::FilesystemAdvisory {
+                        mount_point: &mount.mount_point,
+                        fstype: &mount.fstype,
+                        source: &mount.source,
+                        advice: caveat.advice(),
+                    });
+    }
+    match config.on_weak_fs {
+        OnWeakFs::Warn => Ok(true),
+        OnWeakFs::Skip => Ok(false),
+        OnWeakFs::Refuse => Err(ShremError::WeakFilesystem(entry.path.clone())),
+    }
+}
+
+/// Where the data of a file is, as far as overlays go.
+#[derive(PartialEq, Eq)]
+enum Storage {
+    /// Not on an overlay.
+    Direct,
+    Overlay(Layers),
+    /// On an overlay whose layers cannot be looked at, so the data may be in a lower one.
+    UnknownLayer,
+}
+
+/// Finds out where `entry` is stored if it is on an overlay, and reports it. A file that is only
+/// in a lower layer is refused, since writing to it would only write to a copy of it in the upper
+/// one. Overlays are weak filesystems, so `OnWeakFs::Refuse` has refused them already.
+fn check_overlay(entry: &Entry, config: &Config) -> Result<Storage, ShremError> {
+    let layers = match mounts::mount_of(&entry.dir, Path::new(&entry.name)) {
+        Some((mount, ref path)) if mount.fstype == "overlay" => mount.layers(path),
+        _ => return Ok(Storage::Direct),
+    };
+
+    event::emit(config,
+                Event::Overlay {
+                    path: &entry.path,
+                    upper: layers.as_ref().and_then(|layers| layers.upper.as_deref()),
+                    lower: layers.as_ref().and_then(|layers| layers.lower.as_deref()),
+                    known: layers.is_some(),
+                });
+    match layers {
+        Some(Layers { upper: None, lower: Some(lower) }) => Err(ShremError::LowerLayer(lower)),
+        Some(layers) => Ok(Storage::Overlay(layers)),
+        None => Ok(Storage::UnknownLayer),
+    }
+}
+
+/// Looks for signs that overwriting `file` would write new blocks and leave its data where it
+/// is: a copy-on-write filesystem, or extents shared with other files or snapshots. What is found
+/// is handled as `config.on_cow` says.
+fn check_cow(entry: &Entry, file: &File, config: &Config) -> Result<(), ShremError> {
+    let path = entry.path.as_path();
+    // Filesystems without FIEMAP have nothing to share.
+    let shared = extents::extents(file).map(|extents| extents.iter().any(Extent::is_shared))
+        .unwrap_or(false);
+    let filesystem = extents::cow_filesystem(file)?;
+    if !shared && filesystem.is_none() {
+        return Ok(());
+    }
+
+    if config.on_cow == OnCow::Refuse {
+        return Err(ShremError::CopyOnWrite(path.to_path_buf()));
+    }
+    if shared || extents::first_on(entry.stat.dev()) {
+        event::emit(config, Event::CopyOnWrite { path, filesystem, shared });
+    }
+    Ok(())
+}
+
+/// Truncates the overwritten `file`, hides its name and unlinks it, provided that `entry` still
+/// names it.
+fn remove_file_at(entry: &Entry, file: File, config: &Config) -> Result<(), ShremError> {
+    let path = entry.path.as_path();
+
+    event::emit(config, Event::Removing { path });
+
+    file.set_len(0)?;
+    file.sync_all()?;
+    if !entry.dir.stat(&entry.name)?.is_same(&file)? {
+        return Err(ShremError::Replaced(path.to_path_buf()));
+    }
+    drop(file);
+
+    let renamed = wipe_name(&entry.dir, &entry.name, path, config)?;
+    entry.dir.remove_file(&renamed)?;
+    entry.dir.sync()?;
+
+    event::emit(config, Event::Removed { path });
+
+    Ok(())
+}
+
+/// Hides and removes another name of a regular file that has been overwritten under an earlier
+/// one, provided that `entry` still names that file.
+pub(crate) fn remove_link_at(entry: &Entry, config: &Config) -> Result<(), ShremError> {
+    let path = entry.path.as_path();
+
+    check_interrupt()?;
+
+    if config.no_remove {
+        return Ok(());
+    }
+
+    if config.interactive && !prompt(format_args!("remove file '{}'?", path.display()))? {
+        event::emit(config, Event::Skipped { path });
+        return Ok(());
+    }
+
+    if config.dry_run {
+        event::emit(config, Event::WouldRemove { path, dir: false });
+        return Ok(());
+    }
+
+    event::emit(config, Event::Removing { path });
+
+    if entry.dir.stat(&entry.name)?.id() != entry.stat.id() {
+        return Err(ShremError::Replaced(path.to_path_buf()));
+    }
+
+    let renamed = wipe_name(&entry.dir, &entry.name, path, config)?;
+    entry.dir.remove_file(&renamed)?;
+    entry.dir.sync()?;
+
+    event::emit(config, Event::Removed { path });
+
+    Ok(())
+}
+
+/// Shreds every regular file below `path` and then removes the directories bottom-up. Symlinks
+/// are followed as `config.symlinks` says.
+///
+/// Errors of individual entries are reported as they happen. Unless `config.force` is set, the
+/// walk stops at the first of them. If any entry failed, `ShremError::Incomplete` is returned.
+pub fn shred_tree<P: AsRef<Path>>(path: P, config: &Config) -> Result<(), ShremError> {
+    let config = Config { recursive: true, ..config.clone() };
+    run_plan(Plan::new(path.as_ref(), &config), &config)
+}
+
+/// Removes a file that is neither a regular file nor a directory (symlinks, FIFOs, sockets,
+/// device nodes) without overwriting whatever it refers to.
+pub(crate) fn remove_special_at(entry: &Entry, config: &Config) -> Result<(), ShremError> {
+    let path = entry.path.as_path();
+
+    check_interrupt()?;
+
+    if config.no_remove {
+        return Ok(());
+    }
+
+    if config.interactive && !prompt(format_args!("remove '{}'?", path.display()))? {
+        event::emit(config, Event::Skipped { path });
+        return Ok(());
+    }
+
+    if config.dry_run {
+        event::emit(config, Event::WouldRemove { path, dir: false });
+        return Ok(());
+    }
+
+    entry.dir.remove_file(&entry.name)?;
+    event::emit(config, Event::Removed { path });
+
+    Ok(())
+}
+
+/// Obfuscates the name of an empty directory by renaming it repeatedly and then removes it.
+pub fn shred_dir<P: AsRef<Path>>(path: P, config: &Config) -> Result<(), ShremError> {
+    let path = path.as_ref();
+    let entry = Entry::of(path).map_err(|e| not_found(e, path))?;
+    shred_dir_at(&entry, config)
+}
+
+pub(crate) fn shred_dir_at(entry: &Entry, config: &Config) -> Result<(), ShremError> {
+    let path = entry.path.as_path();
+
+    if config.no_remove {
+        return Ok(());
+    }
+
+    if config.interactive && !prompt(format_args!("remove directory '{}'?", path.display()))? {
+        event::emit(config, Event::Skipped { path });
+        return Ok(());
+    }
+
+    if config.dry_run {
+        event::emit(config, Event::WouldRemove { path, dir: true });
+        return Ok(());
+    }
+
+    remove_dir_at(entry, config)
+}
+
+/// Renames and removes the empty directory `entry`, without asking.
+pub(crate) fn remove_dir_at(entry: &Entry, config: &Config) -> Result<(), ShremError> {
+    let path = entry.path.as_path();
+
+    // Entries that were skipped or failed keep the directory, and renaming it anyway would leave
+    // them somewhere the user does not expect.
+    if !entry.dir.open_dir(&entry.name)?.names()?.is_empty() {
+        return Err(io::Error::from_raw_os_error(libc::ENOTEMPTY).into());
+    }
+
+    event::emit(config, Event::Removing { path });
+
+    let renamed = wipe_name(&entry.dir, &entry.name, path, config)?;
+    entry.dir.remove_dir(&renamed)?;
+    entry.dir.sync()?;
+
+    event::emit(config, Event::Removed { path });
+
+    Ok(())
+}
+
+/// Renames the entry `name` of `dir` as `config.name_wipe` says, so that the original name does
+/// not survive in the directory entry, and syncs the directory after each rename. `path` is the
+/// entry's path for the events. Returns the final name.
+fn wipe_name(dir: &Dir,
+             name: &OsStr,
+             path: &Path,
+             config: &Config)
+             -> Result<OsString, ShremError> {
+    let len = name.as_bytes().len();
+    let lengths = match config.name_wipe {
+        NameWipe::Shorten => (1..len + 1).rev().collect(),
+        NameWipe::Random(rounds) => vec![len; rounds],
+    };
+
+    let mut name = name.to_os_string();
+    let mut path = path.to_path_buf();
+    'lengths: for n in lengths {
+        let new_name = loop {
+            let taken = |s: &str| dir.exists(OsStr::new(s));
+            let new_name = match config.name_wipe {
+                NameWipe::Shorten => new_name(n, taken),
+                NameWipe::Random(_) => random_name(n, taken)?,
+            };
+            let new_name = match new_name {
+                None => break 'lengths,
+                Some(new_name) => OsString::from(new_name),
+            };
+            // Another worker, or another process, may have taken the name since it was checked.
+            match dir.rename(&name, &new_name) {
+                Err(ref e) if e.raw_os_error() == Some(libc::EEXIST) => continue,
+                result => result?,
+            }
+            break new_name;
+        };
+
+        dir.sync()?;
+        let new_path = path.with_file_name(&new_name);
+        event::emit(config, Event::Renamed { from: &path, to: &new_path });
+        name = new_name;
+        path = new_path;
+    }
+
+    Ok(name)
+}
+
+/// Asks a yes/no question on stderr, like `rm -i` does, so that stdout stays clean for `--output`.
+fn prompt(config: fmt::Arguments) -> io::Result<bool> {
+    eprint!("{} ", config);
+    io::stderr().flush()?;
+    let mut s = String::new();
+    io::stdin().read_line(&mut s)?;
+    match s.chars().next() {
+        Some('y') | Some('Y') => Ok(true),
+        _ => Ok(false),
+    }
+}
+
+/// Characters of the names that entries are renamed to.
+static NAME_CHARS: &[u8] = b"0123456789abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ_";
+
+/// Finds an unused name of `length` characters in the directory of `path`, trying `0`, `1`, ...
+/// in order. Returns `None` if all names of that length are taken.
+pub fn generate_new_path<P: AsRef<Path>>(path: P, length: usize) -> Option<PathBuf> {
+    let path = path.as_ref();
+    new_name(length, |s| fs::symlink_metadata(path.with_file_name(s)).is_ok())
+        .map(|name| path.with_file_name(name))
+}
+
+/// Finds an unused name of `length` random characters in the directory of `path`. Returns `None`
+/// if a hundred tries were all taken, which only happens when few names of that length are free.
+pub fn generate_random_path<P: AsRef<Path>>(path: P, length: usize) -> io::Result<Option<PathBuf>> {
+    let path = path.as_ref();
+    Ok(random_name(length, |s| fs::symlink_metadata(path.with_file_name(s)).is_ok())?
+        .map(|name| path.with_file_name(name)))
+}
+
+/// The first name of `length` characters, in the order `0`, `1`, ..., for which `taken` is false.
+fn new_name<F: Fn(&str) -> bool>(length: usize, taken: F) -> Option<String> {
+    let mut idxs = vec![0; length];
+    let mut s = String::with_capacity(length);
+    while idxs[0] < NAME_CHARS.len() {
+        s.clear();
+        s.extend(idxs.iter().map(|&i| char::from(NAME_CHARS[i])));
+        if !taken(&s) {
+            return Some(s);
+        }
+
+        for (i, e) in idxs.iter_mut().enumerate().rev() {
+            *e += 1;
+            if i != 0 && *e == NAME_CHARS.len() {
+                *e = 0;
+            } else {
+                break;
+            }
+        }
+    }
+
+    None
+}
+
+/// A random name of `length` characters for which `taken` is false, if one is found in a hundred
+/// tries.
+fn random_name<F: Fn(&str) -> bool>(length: usize, taken: F) -> io::Result<Option<String>> {
+    let mut rng = native::Rng::from_urandom()?;
+
+    let mut s = String::with_capacity(length);
+    for _ in 0..100 {
+        s.clear();
+        s.extend((0..length).map(|_| {
+            char::from(NAME_CHARS[(rng.next_u64() % NAME_CHARS.len() as u64) as usize])
+        }));
+        if !taken(&s) {
+            return Ok(Some(s));
+        }
+    }
+
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::process;
+    use std::sync::{Arc, Mutex};
+
+    use super::{random_name, NameWipe, Shredder, NAME_CHARS};
+    use event::Event;
+
+    #[test]
+    fn random_names() {
+        let name = random_name(12, |_| false).unwrap().unwrap();
+        assert_eq!(name.len(), 12);
+        assert!(name.bytes().all(|b| NAME_CHARS.contains(&b)));
+        assert_ne!(random_name(12, |_| false).unwrap().unwrap(), name);
+
+        assert_eq!(random_name(1, |_| true).unwrap(), None);
+    }
+
+    #[test]
+    fn random_name_wiping_renames_once_per_round() {
+        let path = env::temp_dir().join(format!("shrem-test-random-names-{}", process::id()));
+        fs::write(&path, b"data").unwrap();
+
+        let renames = Arc::new(Mutex::new(Vec::<PathBuf>::new()));
+        let shredder = {
+            let renames = renames.clone();
+            Shredder::new()
+                .passes(1)
+                .name_wipe(NameWipe::from_name("random", 3).unwrap())
+                .on_event(move |event| if let Event::Renamed { to, .. } = *event {
+                    renames.lock().unwrap().push(to.to_path_buf());
+                })
+        };
+        shredder.shred(&path).unwrap();
+        assert!(!path.exists());
+
+        let renames = renames.lock().unwrap();
+        assert_eq!(renames.len(), 3);
+        for to in renames.iter() {
+            assert_eq!(to.parent(), path.parent());
+            assert_eq!(to.file_name().unwrap().len(), path.file_name().unwrap().len());
+            assert!(!to.exists());
+        }
+    }
+}
@@ -1,34 +1,61 @@
 extern crate clap;
+extern crate libc;
+extern crate shrem;
 
 use clap::{App, Arg};
-use std::error::Error;
-use std::fmt::{Display, Formatter};
-use std::fmt;
-use std::fs;
-use std::io::Write;
-use std::io;
+use std::io::{self, Write};
 use std::path::{Path, PathBuf};
-use std::process::{Command, ExitStatus};
-use std::result::Result;
-
-#[derive(Debug, Copy, Clone)]
-struct Config {
-    recursive: bool,
-    force: bool,
-    verbose: bool,
-    interactive: bool,
-    preserve_root: bool,
-    no_remove: bool,
-    iterations: Option<usize>,
-}
-
-fn main() {
-    let matches = App::new("shrem")
+use std::sync::Arc;
+use std::time::Instant;
+
+use progress::{Progress, Records};
+use settings::Settings;
+use signals::Tracker;
+use shrem::backend::{self, BackendKind};
+use shrem::event::{self, Event, Observer, OutputFormat};
+use shrem::journal;
+use shrem::leftovers::{self, Leftover};
+use shrem::method;
+use shrem::report::{self, ReportFormat};
+use shrem::{Config, HardLinks, NameWipe, OnCow, OnWeakFs, ShremError, Symlinks,
+            DEFAULT_RENAME_ROUNDS, DEFAULT_THROUGHPUT};
+
+mod progress;
+mod settings;
+mod signals;
+
+/// On/off options, each with the option that turns it off again, which overrides configuration
+/// files and `SHREM_OPTS`.
+static NEGATIONS: &[(&str, &str)] = &[("force", "no-force"),
+                                      ("recursive", "no-recursive"),
+                                      ("shred-link-targets", "no-shred-link-targets"),
+                                      ("check-extents", "no-check-extents"),
+                                      ("forensic-verify", "no-forensic-verify"),
+                                      ("verbose", "no-verbose"),
+                                      ("interactive", "no-interactive"),
+                                      ("no-remove", "remove"),
+                                      ("no-zero", "zero"),
+                                      ("verify", "no-verify"),
+                                      ("progress", "no-progress"),
+                                      ("no-journal", "journal"),
+                                      ("dry-run", "no-dry-run"),
+                                      ("report-hash", "no-report-hash")];
+
+fn app<'a>(method_names: &'a [&'a str]) -> App<'a, 'a> {
+    let app = App::new("shrem")
         .version("0.1.0")
         .about("Overwrite the specified FILE(s) repeatedly and then remove it")
+        .after_help("On/off options can be turned off with --no-OPTION (--remove, --zero and \
+                     --journal for --no-remove, --no-zero and --no-journal), e.g. when a \
+                     configuration file or SHREM_OPTS turns them on.")
         .arg(Arg::with_name("FILE")
             .multiple(true)
             .index(1))
+        .arg(Arg::with_name("profile")
+            .long("profile")
+            .takes_value(true)
+            .value_name("NAME")
+            .help("Use the settings of [profile.NAME] from the configuration files"))
         .arg(Arg::with_name("force")
             .short("f")
             .long("force")
@@ -37,6 +64,42 @@ fn main() {
             .short("r")
             .long("recursive")
             .help("Remove directories and their contents recursively"))
+        .arg(Arg::with_name("P")
+            .short("P")
+            .long("no-dereference")
+            .help("Never follow symlinks; only remove them (default)"))
+        .arg(Arg::with_name("H")
+            .short("H")
+            .long("dereference-command-line")
+            .help("Follow symlinks given on the command line and shred what they point to"))
+        .arg(Arg::with_name("L")
+            .short("L")
+            .long("dereference")
+            .help("Follow all symlinks and shred what they point to"))
+        .arg(Arg::with_name("shred-link-targets")
+            .long("shred-link-targets")
+            .help("Overwrite the files that symlinks which are not followed point to, but remove only the links"))
+        .arg(Arg::with_name("hardlinks")
+            .long("hardlinks")
+            .takes_value(true)
+            .possible_values(&["shred-all", "skip", "overwrite-only"])
+            .help("What to do with files that have other hard links (default: warn and ask)"))
+        .arg(Arg::with_name("check-extents")
+            .long("check-extents")
+            .help("Check after each pass that the data was overwritten in place (native engine only)"))
+        .arg(Arg::with_name("forensic-verify")
+            .long("forensic-verify")
+            .help("Check on the block device that samples of the original data are gone (needs read access to the device)"))
+        .arg(Arg::with_name("on-cow")
+            .long("on-cow")
+            .takes_value(true)
+            .possible_values(&["warn", "refuse", "proceed"])
+            .help("What to do with files on copy-on-write storage, where overwriting leaves the old data (default: warn)"))
+        .arg(Arg::with_name("on-weak-fs")
+            .long("on-weak-fs")
+            .takes_value(true)
+            .possible_values(&["warn", "skip", "refuse"])
+            .help("What to do with files on filesystems where overwriting is not reliable, e.g. tmpfs, NFS or f2fs (default: warn)"))
         .arg(Arg::with_name("verbose")
             .short("v")
             .long("verbose")
@@ -44,7 +107,14 @@ fn main() {
         .arg(Arg::with_name("interactive")
             .short("i")
             .long("interactive")
+            .conflicts_with("jobs")
             .help("Prompt before removal"))
+        .arg(Arg::with_name("jobs")
+            .short("j")
+            .long("jobs")
+            .takes_value(true)
+            .value_name("N")
+            .help("Shred N files at a time, spread over the devices they are on"))
         .arg(Arg::with_name("preserve-root")
             .long("preserve-root")
             .conflicts_with("no-preserve-root")
@@ -56,259 +126,437 @@ fn main() {
         .arg(Arg::with_name("no-remove")
             .long("no-remove")
             .help("Don't remove files (only overwrite)"))
+        .arg(Arg::with_name("no-zero")
+            .long("no-zero")
+            .help("Don't add a final overwrite with zeros"))
+        .arg(Arg::with_name("wipe-names")
+            .long("wipe-names")
+            .takes_value(true)
+            .possible_values(&["shorten", "random"])
+            .help("Rename before removal through shorter names, or through random ones (default: shorten)"))
+        .arg(Arg::with_name("rename-rounds")
+            .long("rename-rounds")
+            .takes_value(true)
+            .value_name("N")
+            .help("Number of random names with --wipe-names=random (default: 3)"))
+        .arg(Arg::with_name("backend")
+            .long("backend")
+            .takes_value(true)
+            .env("SHREM_BACKEND")
+            .possible_values(backend::NAMES)
+            .help("Program used to overwrite files (default: native)"))
+        .arg(Arg::with_name("list-backends")
+            .long("list-backends")
+            .help("List the backends and whether they are installed"))
         .arg(Arg::with_name("N")
             .short("n")
             .long("iterations")
             .help("Overwrite N times instead of default (3)")
             .takes_value(true))
-        .get_matches();
+        .arg(Arg::with_name("method")
+            .long("method")
+            .takes_value(true)
+            .possible_values(method_names)
+            .conflicts_with_all(&["N", "no-zero"])
+            .help("Overwrite with the passes of a standard scheme"))
+        .arg(Arg::with_name("verify")
+            .long("verify")
+            .help("Read files back after the last pass and check what was written"))
+        .arg(Arg::with_name("output")
+            .long("output")
+            .takes_value(true)
+            .possible_values(&["text", "json"])
+            .help("Print progress as text (with -v) or as one JSON object per line"))
+        .arg(Arg::with_name("progress")
+            .long("progress")
+            .help("Show a status line with the current file, bytes done, speed and ETA"))
+        .arg(Arg::with_name("progress-fd")
+            .long("progress-fd")
+            .takes_value(true)
+            .value_name("N")
+            .help("Write progress records (file index, pass, bytes, total) to file descriptor N"))
+        .arg(Arg::with_name("resume")
+            .long("resume")
+            .conflicts_with_all(&["FILE", "dry-run"])
+            .help("Finish the jobs in the journal that were interrupted"))
+        .arg(Arg::with_name("no-journal")
+            .long("no-journal")
+            .conflicts_with("resume")
+            .help("Don't record shredding in the journal"))
+        .arg(Arg::with_name("dry-run")
+            .long("dry-run")
+            .help("Show what would be shredded and removed without touching anything"))
+        .arg(Arg::with_name("throughput")
+            .long("throughput")
+            .takes_value(true)
+            .value_name("MB/s")
+            .help("Write speed assumed for the --dry-run time estimate (default: 100)"))
+        .arg(Arg::with_name("report")
+            .long("report")
+            .takes_value(true)
+            .value_name("FILE")
+            .conflicts_with_all(&["dry-run", "report-dir"])
+            .help("Write a certificate of destruction to FILE"))
+        .arg(Arg::with_name("report-dir")
+            .long("report-dir")
+            .takes_value(true)
+            .value_name("DIR")
+            .conflicts_with("dry-run")
+            .help("Write a certificate of destruction to a new file in DIR"))
+        .arg(Arg::with_name("report-format")
+            .long("report-format")
+            .takes_value(true)
+            .possible_values(&["json", "text"])
+            .help("Format of the report (default: json)"))
+        .arg(Arg::with_name("report-hash")
+            .long("report-hash")
+            .help("Record the SHA-256 of each file's contents before overwriting it"))
+        .arg(Arg::with_name("report-key")
+            .long("report-key")
+            .takes_value(true)
+            .value_name("KEY")
+            .help("Sign the report with the Ed25519 private key in KEY (PEM, needs openssl)"))
+        .arg(Arg::with_name("scan-leftovers")
+            .long("scan-leftovers")
+            .takes_value(true)
+            .value_name("DIR")
+            .conflicts_with_all(&["FILE", "resume"])
+            .help("Find entries left behind by interrupted runs in DIR and offer to shred them"))
+        .arg(Arg::with_name("yes")
+            .long("yes")
+            .requires("scan-leftovers")
+            .help("Shred what --scan-leftovers finds without asking (-f does not imply this)"));
+
+    NEGATIONS.iter().fold(app, |app, &(name, negation)| {
+        app.arg(Arg::with_name(negation)
+            .long(negation)
+            .hidden(true)
+            .overrides_with(name))
+    })
+}
 
-    let config = Config {
+fn main() {
+    let method_names = method::METHODS.iter().map(|m| m.name).collect::<Vec<_>>();
+
+    let cli = app(&method_names).get_matches();
+    let matches = match Settings::load(cli, |args| app(&method_names).get_matches_from_safe(args)) {
+        Ok(settings) => settings,
+        Err(e) => {
+            eprintln!("shrem: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    // Options that conflict with each other are taken from the same source.
+    let passes = matches.first_setting(&["method", "N", "no-zero", "zero"]);
+    let root = matches.first_setting(&["preserve-root", "no-preserve-root"]);
+    let symlinks = matches.first_setting(&["P", "H", "L"]);
+
+    let mut config = Config {
         recursive: matches.is_present("recursive"),
         force: matches.is_present("force"),
         verbose: matches.is_present("verbose"),
         interactive: matches.is_present("interactive"),
-        preserve_root: matches.is_present("preserve-root") ||
-                       !matches.is_present("no-preserve-root"),
+        preserve_root: !root.is_some_and(|m| m.is_present("no-preserve-root")),
         no_remove: matches.is_present("no-remove"),
-        iterations: matches.value_of("N")
+        zero: !passes.is_some_and(|m| m.is_present("no-zero")),
+        backend: matches.value_of("backend")
+            .and_then(BackendKind::from_name)
+            .unwrap_or(BackendKind::Native),
+        iterations: passes.and_then(|m| m.value_of("N"))
             .and_then(|s| s.parse::<usize>().ok()),
+        method: passes.and_then(|m| m.value_of("method")).and_then(method::find),
+        verify: matches.is_present("verify"),
+        output: matches.value_of("output")
+            .and_then(OutputFormat::from_name)
+            .unwrap_or(OutputFormat::Text),
+        report: matches.first_setting(&["report", "report-dir"]).is_some(),
+        report_hash: matches.is_present("report-hash"),
+        dry_run: matches.is_present("dry-run"),
+        throughput: matches.value_of("throughput")
+            .and_then(|s| s.parse::<f64>().ok())
+            .filter(|&t| t > 0.0)
+            .unwrap_or(DEFAULT_THROUGHPUT) * 1_000_000.0,
+        observer: None,
+        jobs: matches.value_of("jobs")
+            .and_then(|s| s.parse::<usize>().ok())
+            .unwrap_or(1)
+            .max(1),
+        name_wipe: NameWipe::from_name(matches.value_of("wipe-names").unwrap_or("shorten"),
+                                       matches.value_of("rename-rounds")
+                                           .and_then(|s| s.parse::<usize>().ok())
+                                           .unwrap_or(DEFAULT_RENAME_ROUNDS))
+            .unwrap_or(NameWipe::Shorten),
+        symlinks: match symlinks {
+            Some(m) if m.is_present("L") => Symlinks::All,
+            Some(m) if m.is_present("H") => Symlinks::CommandLine,
+            _ => Symlinks::Never,
+        },
+        shred_link_targets: matches.is_present("shred-link-targets"),
+        hard_links: matches.value_of("hardlinks")
+            .and_then(HardLinks::from_name)
+            .unwrap_or(HardLinks::Ask),
+        on_cow: matches.value_of("on-cow").and_then(OnCow::from_name).unwrap_or(OnCow::Warn),
+        check_extents: matches.is_present("check-extents"),
+        forensic_verify: matches.is_present("forensic-verify"),
+        on_weak_fs: matches.value_of("on-weak-fs")
+            .and_then(OnWeakFs::from_name)
+            .unwrap_or(OnWeakFs::Warn),
     };
 
-    if config.recursive {
-        if let Some(iter) = matches.values_of("FILE") {
-            let mut err = false;
-
-            for p in iter {
-                let path = Path::new(p);
-                if path.is_dir() {
-                    if let Err(e) = shred_dir(&path, &config) {
-                        writeln!(io::stderr(),
-                                 "shrem: cannot remove directory '{}': {}",
-                                 path.display(),
-                                 e)
-                            .unwrap();
-                        err = true;
-                    }
-                } else {
-                    if let Err(e) = shred_file(&path, &config) {
-                        writeln!(io::stderr(),
-                                 "shrem: cannot remove file '{}': {}",
-                                 path.display(),
-                                 e)
-                            .unwrap();
-                        err = true;
-                    }
-                }
+    if matches.is_present("list-backends") {
+        for kind in BackendKind::all() {
+            let backend = kind.backend();
+            let status = if backend.is_available() { "available" } else { "not installed" };
+            if kind.name() == backend.name() {
+                println!("{:<14} {}", kind.name(), status);
+            } else {
+                println!("{:<14} {} ({})", kind.name(), status, backend.name());
+            }
+        }
+        return;
+    }
+
+    if matches.is_present("FILE") && !config.backend.backend().is_available() {
+        eprintln!("shrem: {}",
+                  ShremError::BackendUnavailable(config.backend.backend().name()));
+        std::process::exit(1);
+    }
+
+    // Only the real command line says what to scan, and whether to go ahead without asking.
+    if let Some(dir) = matches.command_line().value_of("scan-leftovers") {
+        std::process::exit(scan_leftovers(Path::new(dir),
+                                          !matches.is_present("no-journal"),
+                                          matches.command_line().is_present("yes"),
+                                          &config));
+    }
+
+    let resume = matches.is_present("resume");
 
-                if err && !config.force {
+    if matches.is_present("FILE") || resume {
+        let start = Instant::now();
+        let paths = matches.values_of("FILE")
+            .map(|paths| paths.map(Path::new).collect::<Vec<_>>())
+            .unwrap_or_default();
+
+        let journal = if config.dry_run || matches.is_present("no-journal") {
+            None
+        } else {
+            journal::default_path()
+        };
+        if let Some(ref path) = journal {
+            if let Err(e) = journal::open(path, resume) {
+                eprintln!("shrem: journal '{}': {}", path.display(), e);
+                if resume {
                     std::process::exit(1);
                 }
             }
-
-            if err {
-                std::process::exit(1);
-            }
         }
-    } else {
-        if let Some(paths) = matches.values_of("FILE") {
-            let paths = paths.map(|iter| iter.map(PathBuf::from));
-
-            let mut err = false;
-            for p in paths {
-                if let Err(e) = shred_file(&p, &config) {
-                    err = true;
-
-                    writeln!(io::stderr(),
-                             "shrem: cannot remove '{}': {}",
-                             p.display(),
-                             e)
-                        .unwrap();
+        if resume && journal.is_none() {
+            eprintln!("shrem: cannot find the journal (HOME is not set)");
+            std::process::exit(1);
+        }
 
-                    if !config.force {
+        let progress = if matches.is_present("progress") && !config.dry_run {
+            Some(Arc::new(Progress::new(&paths, &config)))
+        } else {
+            None
+        };
+
+        let records = match matches.value_of("progress-fd") {
+            Some(_) if config.dry_run => None,
+            Some(fd) => {
+                let records = fd.parse()
+                    .map_err(|_| String::from("not a file descriptor"))
+                    .and_then(|fd| Records::open(fd, &config).map_err(|e| e.to_string()));
+                match records {
+                    Ok(records) => Some(records),
+                    Err(e) => {
+                        eprintln!("shrem: --progress-fd {}: {}", fd, e);
                         std::process::exit(1);
                     }
                 }
             }
-
-            if err {
-                std::process::exit(1);
-            }
+            None => None,
+        };
+
+        let tracker = Arc::new(Tracker::new());
+        signals::install(tracker.clone());
+
+        {
+            let progress = progress.clone();
+            let tracker = tracker.clone();
+            config.observer = Some(Observer(Arc::new(move |event| {
+                tracker.update(event);
+                if let Some(ref progress) = progress {
+                    progress.update(event);
+                }
+                if let Some(ref records) = records {
+                    records.update(event);
+                }
+            })));
         }
-    }
-}
-
-#[derive(Debug)]
-enum ShremError {
-    IoError(io::Error),
-    PreservedRootError,
-    ExternalProcessError(ExitStatus),
-    NotFound(PathBuf),
-    IsADirectory(PathBuf),
-}
 
-impl Display for ShremError {
-    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
-        match *self {
-            ShremError::IoError(ref e) => e.fmt(f),
-            ref other => f.write_str(Error::description(other)),
-        }
-    }
-}
+        let mut err = match journal {
+            Some(ref path) if resume => journal::resume(path, &config).is_err(),
+            _ => shrem::shred_all(&paths, &config).is_err(),
+        };
+        close_journal();
 
-impl Error for ShremError {
-    fn description(&self) -> &str {
-        match *self {
-            ShremError::IoError(ref e) => e.description(),
-            ShremError::PreservedRootError => {
-                "It is dangerous to operate on '/' recursively. \
-                 Use --no-preserve-root to override this failsafe."
-            }
-            ShremError::ExternalProcessError(_) => "External process exited with an error.",
-            ShremError::NotFound(_) => "No such file or directory",
-            ShremError::IsADirectory(_) => "Is a directory",
+        if let Some(progress) = progress {
+            progress.finish();
         }
-    }
 
-    fn cause(&self) -> Option<&Error> {
-        if let ShremError::IoError(ref e) = *self {
-            Some(e)
-        } else {
-            None
+        if shrem::is_interrupted() {
+            tracker.print_outcome(&paths, &config);
         }
-    }
-}
 
-impl From<io::Error> for ShremError {
-    fn from(e: io::Error) -> ShremError {
-        ShremError::IoError(e)
-    }
-}
+        event::emit(&config, Event::Summary { duration: start.elapsed() });
 
-fn shred_file<P: AsRef<Path>>(path: P, config: &Config) -> Result<(), ShremError> {
-    let path = path.as_ref();
+        let format = matches.value_of("report-format")
+            .and_then(ReportFormat::from_name)
+            .unwrap_or(ReportFormat::Json);
+        let report = matches.first_setting(&["report", "report-dir"]).map(|m| {
+            match m.value_of("report") {
+                Some(file) => PathBuf::from(file),
+                None => Path::new(m.value_of("report-dir").unwrap()).join(report::file_name(format)),
+            }
+        });
 
-    if !path.exists() {
-        return Err(ShremError::NotFound(path.to_path_buf()));
-    }
+        if let (Some(report), false) = (report, config.dry_run) {
+            let report = report.as_path();
 
-    if path.is_dir() {
-        return Err(ShremError::IsADirectory(path.to_path_buf()));
-    }
+            if let Err(e) = report::write(report, format, &config) {
+                eprintln!("shrem: cannot write report '{}': {}", report.display(), e);
+                err = true;
+            } else if let Some(key) = matches.value_of("report-key") {
+                if let Err(e) = report::sign(report, Path::new(key)) {
+                    eprintln!("shrem: cannot sign report '{}': {}", report.display(), e);
+                    err = true;
+                }
+            }
+        }
 
-    if !config.interactive || prompt(format_args!("remove file '{}'?", path.display()))? {
-        let mut shred_cmd = get_shred_cmd(config);
-        shred_cmd.arg(path.as_os_str());
-        let status = shred_cmd.status()?;
-        if !status.success() {
-            return Err(ShremError::ExternalProcessError(status));
+        if let Some(sig) = signals::interrupted_by() {
+            std::process::exit(128 + sig);
+        }
+        if err {
+            std::process::exit(1);
         }
     }
-
-    Ok(())
 }
 
-fn shred_dir<P: AsRef<Path>>(path: P, config: &Config) -> Result<(), ShremError> {
-    use std::os::unix::ffi::OsStrExt;
+/// `shrem --scan-leftovers DIR`: lists what looks left behind and, once confirmed (or with
+/// `--yes`), shreds it. Returns the exit status.
+fn scan_leftovers(dir: &Path, use_journal: bool, yes: bool, config: &Config) -> i32 {
+    let start = Instant::now();
+
+    let journal = if use_journal { journal::default_path() } else { None };
+    let jobs = match journal {
+        Some(ref path) => {
+            journal::unfinished(path).unwrap_or_else(|e| {
+                eprintln!("shrem: journal '{}': {}", path.display(), e);
+                Vec::new()
+            })
+        }
+        None => Vec::new(),
+    };
 
-    let mut path = path.as_ref().to_path_buf();
+    let found = match leftovers::scan(dir, &jobs) {
+        Ok(found) => found,
+        Err(e) => {
+            eprintln!("shrem: cannot scan '{}': {}", dir.display(), e);
+            return 1;
+        }
+    };
 
-    if !path.exists() {
-        return Err(ShremError::NotFound(path.to_path_buf()));
+    if found.is_empty() {
+        eprintln!("shrem: no leftovers found in '{}'", dir.display());
+        return 0;
     }
 
-    assert!(path.is_dir());
-
-    if config.preserve_root && path.is_absolute() && path.parent().is_none() {
-        return Err(ShremError::PreservedRootError);
+    for leftover in &found {
+        println!("{}", describe(leftover));
     }
 
-    if config.no_remove {
-        return Ok(());
+    if config.dry_run {
+        return 0;
+    }
+    if !yes && !confirm(&format!("shred these {} entries?", found.len())) {
+        return 0;
     }
 
-    if !config.interactive || prompt(format_args!("remove directory '{}'?", path.display()))? {
-        if config.verbose {
-            println!("shrem: {}: removing", path.display());
+    if let Some(ref path) = journal {
+        if let Err(e) = journal::open(path, true) {
+            eprintln!("shrem: journal '{}': {}", path.display(), e);
+            return 1;
         }
+    }
 
-        if let Some(len) = path.file_name().map(|name| name.as_bytes().len()) {
-            for n in (1..len + 1).rev() {
-                let new_path = match generate_new_path(&path, n) {
-                    None => break,
-                    Some(p) => p,
-                };
-
-                if config.verbose {
-                    println!("shrem: {}: renamed to {}",
-                             path.display(),
-                             new_path.display());
-                }
-                fs::rename(&path, &new_path)?;
-                path = new_path;
+    let mut err = false;
+    for leftover in &found {
+        if let Err(e) = leftovers::finish(leftover, config) {
+            event::emit(config, Event::Error { path: &leftover.path, error: &e });
+            err = true;
+            if !config.force {
+                break;
             }
         }
-
-        fs::remove_dir(&path)?;
-        if config.verbose {
-            println!("shrem: {}: removed", path.display());
-        }
     }
 
-    Ok(())
+    close_journal();
+
+    event::emit(config, Event::Summary { duration: start.elapsed() });
+    if err { 1 } else { 0 }
 }
 
-fn get_shred_cmd(config: &Config) -> Command {
-    let mut shred_cmd = Command::new("shred");
-    shred_cmd.arg("-z");
-    if !config.no_remove {
-        shred_cmd.arg("-u");
-    }
-    if config.verbose {
-        shred_cmd.arg("-v");
-    }
-    if let Some(n) = config.iterations {
-        shred_cmd.arg(&format!("-n {}", n));
+/// Stops journaling, which removes the journal if nothing in it is left to be done.
+fn close_journal() {
+    if let Err(e) = journal::close() {
+        eprintln!("shrem: cannot remove the journal: {}", e);
     }
-    shred_cmd
 }
 
-fn prompt(config: fmt::Arguments) -> io::Result<bool> {
-    print!("{} ", config);
-    io::stdout().flush()?;
-    let mut s = String::new();
-    io::stdin().read_line(&mut s)?;
-    match s.chars().next() {
-        Some('y') | Some('Y') => Ok(true),
-        _ => Ok(false),
+fn describe(leftover: &Leftover) -> String {
+    let kind = if leftover.dir { "directory" } else { "file" };
+    match leftover.job {
+        Some(ref job) if job.passes > 0 => {
+            format!("{} ({}, was '{}', {} passes done)",
+                    leftover.path.display(),
+                    kind,
+                    job.original.display(),
+                    job.passes)
+        }
+        Some(ref job) => {
+            format!("{} ({}, was '{}')", leftover.path.display(), kind, job.original.display())
+        }
+        None => format!("{} ({}, obfuscated name)", leftover.path.display(), kind),
     }
 }
 
-fn generate_new_path<P: AsRef<Path>>(path: P, length: usize) -> Option<PathBuf> {
-    let mut path = path.as_ref().to_path_buf();
-
-    static CHARS: &'static [u8] =
-        b"0123456789abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ_";
+/// Asks a yes/no question on stderr.
+fn confirm(question: &str) -> bool {
+    eprint!("shrem: {} ", question);
+    let _ = io::stderr().flush();
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer).is_ok() && answer.trim_start().starts_with(['y', 'Y'])
+}
 
-    let mut idxs = vec![0; length];
-    let mut s = String::with_capacity(length);
-    while idxs[0] < CHARS.len() {
-        s.clear();
-        s.extend(idxs.iter().map(|&i| char::from(CHARS[i])));
-        path.set_file_name(&s);
-        if !path.exists() {
-            return Some(path);
-        }
+#[cfg(test)]
+mod tests {
+    use super::app;
 
-        for (i, e) in idxs.iter_mut().enumerate().rev() {
-            *e += 1;
-            if i != 0 && *e == CHARS.len() {
-                *e = 0;
-            } else {
-                break;
-            }
-        }
+    #[test]
+    fn positionals_are_paths() {
+        let matches = app(&[]).get_matches_from(["shrem", "help", "scan-leftovers", "hel"]);
+        assert_eq!(matches.values_of("FILE").unwrap().collect::<Vec<_>>(),
+                   ["help", "scan-leftovers", "hel"]);
     }
 
-    None
+    #[test]
+    fn yes_needs_scan_leftovers() {
+        assert!(app(&[]).get_matches_from_safe(["shrem", "--yes", "file"]).is_err());
+        let matches = app(&[]).get_matches_from(["shrem", "--scan-leftovers", "dir", "--yes"]);
+        assert_eq!(matches.value_of("scan-leftovers"), Some("dir"));
+    }
 }
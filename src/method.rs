@@ -0,0 +1,110 @@
+//! Overwrite methods: named sequences of passes.
+
+use super::{Config, DEFAULT_ITERATIONS};
+
+/// What a single overwrite pass writes.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Pass {
+    /// Pseudo-random data.
+    Random,
+    /// The given bytes, repeated over the whole file.
+    Pattern(&'static [u8]),
+    /// The bitwise complement of whatever the previous pass wrote.
+    Complement,
+}
+
+/// A named overwrite scheme.
+#[derive(Debug)]
+pub struct Method {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub passes: &'static [Pass],
+}
+
+const ZEROS: Pass = Pass::Pattern(&[0x00]);
+const ONES: Pass = Pass::Pattern(&[0xff]);
+
+pub static METHODS: &[Method] = &[
+    Method {
+        name: "dod",
+        description: "DoD 5220.22-M",
+        passes: &[ZEROS, Pass::Complement, Pass::Random],
+    },
+    Method {
+        name: "gutmann",
+        description: "Gutmann",
+        passes: &GUTMANN,
+    },
+    Method {
+        name: "schneier",
+        description: "Schneier",
+        passes: &[ONES, ZEROS, Pass::Random, Pass::Random, Pass::Random, Pass::Random, Pass::Random],
+    },
+    Method {
+        name: "vsitr",
+        description: "BSI VSITR",
+        passes: &[ZEROS, ONES, ZEROS, ONES, ZEROS, ONES, Pass::Pattern(&[0xaa])],
+    },
+    Method {
+        name: "nist-clear",
+        description: "NIST SP 800-88 Clear",
+        passes: &[ZEROS],
+    },
+];
+
+static GUTMANN: [Pass; 35] = [
+    Pass::Random,
+    Pass::Random,
+    Pass::Random,
+    Pass::Random,
+    Pass::Pattern(&[0x55]),
+    Pass::Pattern(&[0xaa]),
+    Pass::Pattern(&[0x92, 0x49, 0x24]),
+    Pass::Pattern(&[0x49, 0x24, 0x92]),
+    Pass::Pattern(&[0x24, 0x92, 0x49]),
+    Pass::Pattern(&[0x00]),
+    Pass::Pattern(&[0x11]),
+    Pass::Pattern(&[0x22]),
+    Pass::Pattern(&[0x33]),
+    Pass::Pattern(&[0x44]),
+    Pass::Pattern(&[0x55]),
+    Pass::Pattern(&[0x66]),
+    Pass::Pattern(&[0x77]),
+    Pass::Pattern(&[0x88]),
+    Pass::Pattern(&[0x99]),
+    Pass::Pattern(&[0xaa]),
+    Pass::Pattern(&[0xbb]),
+    Pass::Pattern(&[0xcc]),
+    Pass::Pattern(&[0xdd]),
+    Pass::Pattern(&[0xee]),
+    Pass::Pattern(&[0xff]),
+    Pass::Pattern(&[0x92, 0x49, 0x24]),
+    Pass::Pattern(&[0x49, 0x24, 0x92]),
+    Pass::Pattern(&[0x24, 0x92, 0x49]),
+    Pass::Pattern(&[0x6d, 0xb6, 0xdb]),
+    Pass::Pattern(&[0xb6, 0xdb, 0x6d]),
+    Pass::Pattern(&[0xdb, 0x6d, 0xb6]),
+    Pass::Random,
+    Pass::Random,
+    Pass::Random,
+    Pass::Random,
+];
+
+pub fn find(name: &str) -> Option<&'static Method> {
+    METHODS.iter().find(|m| m.name == name)
+}
+
+/// The passes to run for `config`: those of the selected method, or `-n` random passes followed
+/// by a zero pass unless `--no-zero` is given.
+pub fn passes(config: &Config) -> Vec<Pass> {
+    match config.method {
+        Some(method) => method.passes.to_vec(),
+        None => {
+            let mut passes = vec![Pass::Random; config.iterations.unwrap_or(DEFAULT_ITERATIONS)];
+            if config.zero {
+                passes.push(ZEROS);
+            }
+            passes
+        }
+    }
+}
@@ -0,0 +1,306 @@
+//! What a file is mounted on, from `/proc/self/mountinfo`, and how much overwriting it can be
+//! trusted there.
+//!
+//! The mount table is read once per process. Elsewhere than on Linux there is none, and nothing
+//! is found.
+//!
+//! For overlays, the options name the directories the layers are in, where a file of the merged
+//! view can be looked up to find out which layer holds it. Inside a container, they are usually
+//! outside of what can be seen, and so is the answer.
+
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::os::unix::ffi::OsStringExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+
+use at::Dir;
+
+/// A line of `/proc/self/mountinfo`.
+#[derive(Debug)]
+pub(crate) struct Mount {
+    pub id: u32,
+    /// The directory the filesystem is mounted on.
+    pub mount_point: PathBuf,
+    pub fstype: String,
+    pub source: String,
+    /// Per-mount options followed by those of the filesystem, e.g. `data=journal`.
+    pub options: Vec<String>,
+}
+
+impl Mount {
+    /// The value of option `name`, if it is set.
+    pub fn option(&self, name: &str) -> Option<&str> {
+        self.options.iter().find_map(|option| {
+            option.strip_prefix(name).and_then(|rest| rest.strip_prefix('='))
+        })
+    }
+
+    /// Why overwriting files on this mount may not destroy their data, if it may not.
+    pub fn caveat(&self) -> Option<Caveat> {
+        match self.fstype.as_str() {
+            "ext3" | "ext4" if self.option("data") == Some("journal") => Some(Caveat::Journal),
+            "f2fs" | "nilfs2" => Some(Caveat::LogStructured),
+            "tmpfs" | "ramfs" => Some(Caveat::Memory),
+            "nfs" | "nfs4" | "cifs" | "smb3" | "9p" | "ceph" => Some(Caveat::Network),
+            "overlay" => Some(Caveat::Overlay),
+            fstype if fstype == "fuse" || fstype.starts_with("fuse.") || fstype == "fuseblk" => {
+                Some(Caveat::Fuse)
+            }
+            _ => None,
+        }
+    }
+
+    /// Finds `path`, which is on this overlay, in its upper and lower directories. `None` if they
+    /// cannot all be looked at.
+    pub fn layers(&self, path: &Path) -> Option<Layers> {
+        let relative = path.strip_prefix(&self.mount_point).ok()?;
+        // A read-only overlay has no upper directory.
+        let upper_dir = self.option("upperdir").map(PathBuf::from);
+        let lower_dirs = self.lower_dirs();
+        if upper_dir.iter().chain(&lower_dirs).any(|dir| fs::metadata(dir).is_err()) {
+            return None;
+        }
+
+        let find = |dir: &PathBuf| {
+            Some(dir.join(relative)).filter(|path| fs::symlink_metadata(path).is_ok())
+        };
+        Some(Layers {
+            upper: upper_dir.as_ref().and_then(find),
+            lower: lower_dirs.iter().find_map(find),
+        })
+    }
+
+    /// The lower directories of an overlay, topmost first.
+    fn lower_dirs(&self) -> Vec<PathBuf> {
+        let mut dirs = Vec::new();
+        for option in &self.options {
+            if let Some(list) = option.strip_prefix("lowerdir=") {
+                // Separated by colons; `\:` is a colon in a name, and `::` precedes data-only
+                // layers.
+                let mut dir = String::new();
+                let mut chars = list.chars();
+                while let Some(c) = chars.next() {
+                    match c {
+                        '\\' => dir.extend(chars.next()),
+                        ':' => dirs.push(PathBuf::from(std::mem::take(&mut dir))),
+                        c => dir.push(c),
+                    }
+                }
+                dirs.push(PathBuf::from(dir));
+            } else if let Some(dir) = option.strip_prefix("lowerdir+=")
+                .or_else(|| option.strip_prefix("datadir+=")) {
+                dirs.push(PathBuf::from(dir));
+            }
+        }
+        dirs.retain(|dir| dir != Path::new(""));
+        dirs
+    }
+}
+
+/// Where a file in the merged view of an overlay is stored.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct Layers {
+    /// The file in the upper directory, if it is there.
+    pub upper: Option<PathBuf>,
+    /// The topmost copy of it in a lower directory, if there is one. When there is an upper one
+    /// too, this is what it was copied up from, which writing to the file did not change.
+    pub lower: Option<PathBuf>,
+}
+
+/// Why overwriting a file may leave its data behind, depending on the filesystem.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Caveat {
+    /// ext3/ext4 with `data=journal`.
+    Journal,
+    /// f2fs and nilfs2.
+    LogStructured,
+    /// tmpfs and ramfs.
+    Memory,
+    /// NFS, SMB and the like.
+    Network,
+    Fuse,
+    Overlay,
+}
+
+impl Caveat {
+    /// What goes wrong, following the name of the filesystem.
+    pub fn advice(&self) -> &'static str {
+        match *self {
+            Caveat::Journal => {
+                "journals file data (data=journal), so copies of the old contents may stay in the \
+                 journal"
+            }
+            Caveat::LogStructured => {
+                "is log-structured: overwrites go to new places, and the old data stays until it \
+                 is cleaned up (or in checkpoints)"
+            }
+            Caveat::Memory => {
+                "keeps files in memory: overwriting works, but pages that were swapped out may \
+                 remain in swap"
+            }
+            Caveat::Network => {
+                "is a network filesystem: the server decides where data is written and may keep \
+                 caches or snapshots"
+            }
+            Caveat::Fuse => {
+                "is a FUSE filesystem: whether data is overwritten in place is up to the program \
+                 behind it"
+            }
+            Caveat::Overlay => {
+                "is an overlay: writing to a file from a lower layer copies it up, and the lower \
+                 layer keeps the original"
+            }
+        }
+    }
+}
+
+/// The mount that `name` in `dir` is on, with the path of `name`.
+pub(crate) fn mount_of(dir: &Dir, name: &Path) -> Option<(&'static Mount, PathBuf)> {
+    let path = path_of(dir).ok()?.join(name);
+    // The last of the mounts on the longest prefix of the path is the one on top, which is what
+    // `max_by_key` returns.
+    let mount = table()
+        .iter()
+        .filter(|mount| path.starts_with(&mount.mount_point))
+        .max_by_key(|mount| mount.mount_point.components().count())?;
+    Some((mount, path))
+}
+
+/// Whether `mount` has not been asked about before, for advice that is given once per mount.
+pub(crate) fn first_on(mount: &Mount) -> bool {
+    static SEEN: Mutex<Vec<u32>> = Mutex::new(Vec::new());
+
+    let mut seen = SEEN.lock().unwrap();
+    if seen.contains(&mount.id) {
+        false
+    } else {
+        seen.push(mount.id);
+        true
+    }
+}
+
+/// The path that `dir` has now.
+fn path_of(dir: &Dir) -> io::Result<PathBuf> {
+    fs::read_link(format!("/proc/self/fd/{}", dir.as_raw_fd()))
+}
+
+fn table() -> &'static [Mount] {
+    static TABLE: OnceLock<Vec<Mount>> = OnceLock::new();
+
+    TABLE.get_or_init(|| {
+        fs::read_to_string("/proc/self/mountinfo")
+            .map(|s| s.lines().filter_map(parse).collect())
+            .unwrap_or_default()
+    })
+}
+
+/// Parses a line like
+/// `36 35 98:0 / /mnt rw,noatime master:1 - ext4 /dev/sda1 rw,data=journal`.
+fn parse(line: &str) -> Option<Mount> {
+    let mut fields = line.split(' ');
+    let id = fields.next()?.parse().ok()?;
+    let mount_point = PathBuf::from(unescape(fields.nth(3)?));
+    let mut options = fields.next()?.split(',').map(unescape_str).collect::<Vec<_>>();
+    // Optional fields up to the separator.
+    fields.find(|&field| field == "-")?;
+    let fstype = unescape_str(fields.next()?);
+    let source = unescape_str(fields.next()?);
+    options.extend(fields.next()?.split(',').map(unescape_str));
+
+    Some(Mount {
+        id,
+        mount_point,
+        fstype,
+        source,
+        options,
+    })
+}
+
+/// Undoes the octal escapes (`\040` for a space) of a field.
+fn unescape(field: &str) -> OsString {
+    let bytes = field.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let code = bytes.get(i + 1..i + 4)
+            .filter(|_| bytes[i] == b'\\')
+            .and_then(|digits| std::str::from_utf8(digits).ok())
+            .and_then(|digits| u8::from_str_radix(digits, 8).ok());
+        match code {
+            Some(byte) => {
+                out.push(byte);
+                i += 4;
+            }
+            None => {
+                out.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    OsString::from_vec(out)
+}
+
+fn unescape_str(field: &str) -> String {
+    unescape(field).to_string_lossy().into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use super::parse;
+
+    #[test]
+    fn parse_with_optional_fields() {
+        let mount = parse("36 35 98:0 / /mnt rw,noatime shared:1 master:2 - ext4 /dev/sda1 \
+                           rw,data=journal")
+            .unwrap();
+        assert_eq!(mount.id, 36);
+        assert_eq!(mount.mount_point, Path::new("/mnt"));
+        assert_eq!(mount.fstype, "ext4");
+        assert_eq!(mount.source, "/dev/sda1");
+        assert_eq!(mount.options, ["rw", "noatime", "rw", "data=journal"]);
+        assert_eq!(mount.option("data"), Some("journal"));
+        assert_eq!(mount.option("dat"), None);
+    }
+
+    #[test]
+    fn parse_without_optional_fields() {
+        let mount = parse("25 1 0:22 / /run\\040dir rw - tmpfs tmp\\040fs rw,size=10k").unwrap();
+        assert_eq!(mount.mount_point, Path::new("/run dir"));
+        assert_eq!(mount.source, "tmp fs");
+        assert_eq!(mount.option("size"), Some("10k"));
+    }
+
+    #[test]
+    fn parse_bad_lines() {
+        assert!(parse("").is_none());
+        assert!(parse("x 1 0:22 / /run rw - tmpfs tmpfs rw").is_none());
+        assert!(parse("25 1 0:22 / /run rw shared:1 tmpfs tmpfs rw").is_none());
+        assert!(parse("25 1 0:22 / /run rw - tmpfs tmpfs").is_none());
+    }
+
+    fn lower_dirs(options: &str) -> Vec<PathBuf> {
+        let line = format!("40 1 0:40 / /merged rw - overlay overlay rw,{}", options);
+        parse(&line).unwrap().lower_dirs()
+    }
+
+    #[test]
+    fn lower_dirs_in_one_option() {
+        assert_eq!(lower_dirs("lowerdir=/l1:/l2,upperdir=/u,workdir=/w"),
+                   [Path::new("/l1"), Path::new("/l2")]);
+        assert_eq!(lower_dirs("lowerdir=/a\\:b:/c"), [Path::new("/a:b"), Path::new("/c")]);
+        // Data-only layers follow `::`.
+        assert_eq!(lower_dirs("lowerdir=/l1::/data"), [Path::new("/l1"), Path::new("/data")]);
+        assert_eq!(lower_dirs("lowerdir=/with\\040space"), [Path::new("/with space")]);
+    }
+
+    #[test]
+    fn lower_dirs_added_one_by_one() {
+        assert_eq!(lower_dirs("lowerdir+=/l1,lowerdir+=/l2,datadir+=/data,upperdir=/u"),
+                   [Path::new("/l1"), Path::new("/l2"), Path::new("/data")]);
+        assert!(lower_dirs("upperdir=/u,workdir=/w").is_empty());
+    }
+}
@@ -0,0 +1,313 @@
+//! Built-in overwrite engine, used instead of spawning an external `shred` process.
+
+use std::cmp;
+use std::fmt::{self, Display, Formatter};
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::time::Instant;
+
+use super::{is_interrupted, Config, ShremError};
+use backend::{Backend, Target};
+use event::{self, Event};
+use extents::{self, Extent, Placement};
+use method::{self, Pass};
+
+const BUF_SIZE: usize = 64 * 1024;
+/// How often `Event::Progress` is emitted during a pass, in bytes.
+const PROGRESS_STEP: u64 = 4 * 1024 * 1024;
+
+/// The built-in engine. Always available.
+pub struct Native;
+
+impl Backend for Native {
+    fn name(&self) -> &'static str {
+        "native"
+    }
+
+    fn is_available(&self) -> bool {
+        true
+    }
+
+    fn passes(&self, config: &Config) -> Option<usize> {
+        Some(method::passes(config).len())
+    }
+
+    fn overwrite(&self, target: &Target, config: &Config) -> Result<(), ShremError> {
+        overwrite_from(target.path, target.file, config, 0).map(|_| ())
+    }
+}
+
+/// Overwrites `file` with the passes of the configured method, skipping the first `done` of them,
+/// which have been written before, and verifies it if asked to. The file must have been opened for
+/// reading too if `config.verify` is set.
+///
+/// With `config.check_extents`, the extents of the file are compared with those it had before
+/// after each pass, and where the data ended up is returned.
+pub(crate) fn overwrite_from(path: &Path,
+                             mut file: &File,
+                             config: &Config,
+                             done: usize)
+                             -> Result<Option<Placement>, ShremError> {
+    let passes = method::passes(config);
+    let verifying = config.verify && done < passes.len();
+
+    let len = file.metadata()?.len();
+
+    let original = if config.check_extents {
+        Some(layout(file)?)
+    } else {
+        None
+    };
+    let mut placement = original.as_ref().map(|_| Placement::InPlace);
+
+    let mut rng = Rng::from_urandom()?;
+    let mut buf = vec![0; BUF_SIZE];
+    let mut previous = Fill {
+        source: Source::Pattern(&[0]),
+        invert: false,
+    };
+    if done > 0 {
+        // Random data written before cannot be reproduced; a complement of it becomes random.
+        previous = next_fill(&passes[done - 1], &previous, &mut rng)?;
+    }
+
+    for (i, pass) in passes.iter().enumerate().skip(done) {
+        let mut fill = next_fill(pass, &previous, &mut rng)?;
+
+        event::emit(config,
+                    Event::PassStart {
+                        path,
+                        pass: i + 1,
+                        total: passes.len(),
+                        pattern: &fill.to_string(),
+                    });
+
+        let start = Instant::now();
+        previous = fill.clone();
+        overwrite(&mut file, len, &mut buf, &mut fill, |bytes| {
+            event::emit(config, Event::Progress { path, pass: i + 1, bytes });
+        })?;
+
+        if let Some(ref original) = original {
+            let relocated = layout(file)? != *original;
+            if relocated {
+                placement = Some(Placement::Relocated);
+            }
+            event::emit(config, Event::ExtentsChecked { path, pass: i + 1, relocated });
+        }
+
+        event::emit(config,
+                    Event::PassDone {
+                        path,
+                        pass: i + 1,
+                        bytes: len,
+                        duration: start.elapsed(),
+                    });
+    }
+
+    if verifying {
+        event::emit(config, Event::Verifying { path });
+
+        let start = Instant::now();
+        verify(&mut file, len, &mut buf, &mut previous)?;
+
+        event::emit(config, Event::Verified { path, duration: start.elapsed() });
+    }
+
+    Ok(placement)
+}
+
+/// Where the data of `file` is, for `config.check_extents`.
+fn layout(file: &File) -> Result<Vec<Extent>, ShremError> {
+    extents::layout(file).map_err(|e| if e.raw_os_error() == Some(libc::EOPNOTSUPP) {
+        ShremError::Unsupported("--check-extents on a filesystem without FIEMAP")
+    } else {
+        e.into()
+    })
+}
+
+/// The data for `pass`, which follows a pass that wrote `previous`.
+fn next_fill(pass: &Pass, previous: &Fill, rng: &mut Rng) -> io::Result<Fill> {
+    let fill = match *pass {
+        Pass::Random => {
+            let fill = Fill {
+                source: Source::Random(rng.clone()),
+                invert: false,
+            };
+            // Advance the shared generator so that consecutive random passes differ.
+            *rng = Rng::from_urandom()?;
+            fill
+        }
+        Pass::Pattern(bytes) => {
+            Fill {
+                source: Source::Pattern(bytes),
+                invert: false,
+            }
+        }
+        Pass::Complement => {
+            Fill {
+                source: previous.source.clone(),
+                invert: !previous.invert,
+            }
+        }
+    };
+    Ok(fill)
+}
+
+/// Writes `len` bytes produced by `fill` from the start of `file` and flushes them to disk.
+/// `progress` is called with the number of bytes written so far every `PROGRESS_STEP` bytes.
+/// Once `interrupt()` has been called, flushes what has been written and stops with
+/// `ShremError::Interrupted`.
+fn overwrite<F>(file: &mut &File,
+                len: u64,
+                buf: &mut [u8],
+                fill: &mut Fill,
+                mut progress: F)
+                -> Result<(), ShremError>
+    where F: FnMut(u64)
+{
+    file.seek(SeekFrom::Start(0))?;
+
+    let mut offset = 0;
+    while offset < len {
+        if is_interrupted() {
+            file.sync_data()?;
+            return Err(ShremError::Interrupted);
+        }
+        let n = cmp::min(len - offset, buf.len() as u64) as usize;
+        fill.fill(offset, &mut buf[..n]);
+        file.write_all(&buf[..n])?;
+        offset += n as u64;
+        if offset % PROGRESS_STEP == 0 && offset < len {
+            progress(offset);
+        }
+    }
+
+    file.sync_data()?;
+    Ok(())
+}
+
+/// Reads `file` back and compares it with what `fill` produces. The page cache is dropped first,
+/// so the data comes from the device rather than from memory.
+fn verify(file: &mut &File, len: u64, buf: &mut [u8], fill: &mut Fill) -> Result<(), ShremError> {
+    use std::os::unix::io::AsRawFd;
+
+    // The pages are clean after the fsync of the last pass, so they can be evicted.
+    let ret = unsafe { libc::posix_fadvise(file.as_raw_fd(), 0, 0, libc::POSIX_FADV_DONTNEED) };
+    if ret != 0 {
+        return Err(io::Error::from_raw_os_error(ret).into());
+    }
+
+    file.seek(SeekFrom::Start(0))?;
+
+    let mut expected = vec![0; buf.len()];
+    let mut offset = 0;
+    while offset < len {
+        let n = cmp::min(len - offset, buf.len() as u64) as usize;
+        file.read_exact(&mut buf[..n])?;
+        fill.fill(offset, &mut expected[..n]);
+        if let Some(i) = buf[..n].iter().zip(&expected[..n]).position(|(a, b)| a != b) {
+            return Err(ShremError::VerificationFailed(offset + i as u64));
+        }
+        offset += n as u64;
+    }
+
+    Ok(())
+}
+
+#[derive(Clone)]
+enum Source {
+    /// A generator in the state it was in at the start of the pass, so that a following
+    /// complement pass can reproduce the stream.
+    Random(Rng),
+    Pattern(&'static [u8]),
+}
+
+/// The data written by one pass.
+#[derive(Clone)]
+struct Fill {
+    source: Source,
+    invert: bool,
+}
+
+impl Fill {
+    /// Fills `buf` with the bytes destined for file offset `offset`.
+    fn fill(&mut self, offset: u64, buf: &mut [u8]) {
+        match self.source {
+            Source::Random(ref mut rng) => rng.fill(buf),
+            Source::Pattern(pattern) => {
+                let len = pattern.len() as u64;
+                for (i, b) in buf.iter_mut().enumerate() {
+                    *b = pattern[((offset + i as u64) % len) as usize];
+                }
+            }
+        }
+
+        if self.invert {
+            for b in buf {
+                *b = !*b;
+            }
+        }
+    }
+}
+
+impl Display for Fill {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self.source {
+            Source::Random(_) if self.invert => f.write_str("inverted random"),
+            Source::Random(_) => f.write_str("random"),
+            Source::Pattern(pattern) => {
+                for i in 0..3 {
+                    let b = pattern[i % pattern.len()];
+                    write!(f, "{:02x}", if self.invert { !b } else { b })?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// xorshift128+ generator seeded from `/dev/urandom`.
+///
+/// It is not cryptographically secure, but neither is the ISAAC stream used by `shred`; the
+/// overwrite only needs to be unpredictable enough not to compress or deduplicate.
+#[derive(Clone)]
+pub(crate) struct Rng {
+    s0: u64,
+    s1: u64,
+}
+
+impl Rng {
+    pub(crate) fn from_urandom() -> io::Result<Rng> {
+        let mut seed = [0; 16];
+        File::open("/dev/urandom")?.read_exact(&mut seed)?;
+
+        let mut s0 = [0; 8];
+        let mut s1 = [0; 8];
+        s0.copy_from_slice(&seed[..8]);
+        s1.copy_from_slice(&seed[8..]);
+
+        Ok(Rng {
+            s0: u64::from_le_bytes(s0),
+            // The state must not be all zeros.
+            s1: u64::from_le_bytes(s1) | 1,
+        })
+    }
+
+    pub(crate) fn next_u64(&mut self) -> u64 {
+        let mut x = self.s0;
+        let y = self.s1;
+        self.s0 = y;
+        x ^= x << 23;
+        self.s1 = x ^ y ^ (x >> 17) ^ (y >> 26);
+        self.s1.wrapping_add(y)
+    }
+
+    fn fill(&mut self, buf: &mut [u8]) {
+        for chunk in buf.chunks_mut(8) {
+            let bytes = self.next_u64().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+}
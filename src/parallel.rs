@@ -0,0 +1,304 @@
+//! `--jobs`: shredding on a pool of worker threads, scheduled per device.
+//!
+//! The arguments are walked on the calling thread while the workers shred what has been found so
+//! far. Regular files (and other non-directories) are handed to the workers, each of which picks
+//! the device that currently has the fewest workers busy on it, so that all disks are kept working
+//! without piling every worker onto one of them.
+//!
+//! Entries are queued with the directory they are in, which stays open until they are done. The
+//! walk waits while many entries are queued, which keeps the number of open directories bounded;
+//! the limit on open files is raised as far as it goes on top of that.
+//!
+//! A directory is removed once everything queued before it is done, which includes everything in
+//! it. Another name of a file that is overwritten under an earlier one is only removed once the
+//! overwriting is done, as in a sequential run.
+//!
+//! Each entry's output is collected while it is being shredded and printed once all entries
+//! before it have been printed, so the output is in the same order as a sequential run.
+
+use std::collections::{BTreeMap, BTreeSet, HashSet, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::sync::{Condvar, Mutex};
+use std::thread;
+
+use super::{check_interrupt, is_interrupted, report_error, run_step, Config, ShremError};
+use event::{self, Line};
+use plan::{Plan, Step};
+
+/// Number of entries that may be queued and not done yet before the walk waits, unless the limit
+/// on open files is lower.
+const QUEUED: usize = 1024;
+
+struct Task {
+    index: usize,
+    path: PathBuf,
+    /// Failures found while walking the arguments are reported in their place in the output.
+    step: Step,
+}
+
+/// Pending tasks, one queue per device, and directories to remove.
+struct Queues {
+    devices: Vec<Device>,
+    /// Directories, in the order they were found.
+    dirs: VecDeque<Task>,
+    /// Number of tasks queued so far; the index of the next one.
+    count: usize,
+    /// Tasks queued or running.
+    unfinished: BTreeSet<usize>,
+    /// Files being overwritten, by device and inode number.
+    overwriting: HashSet<(u64, u64)>,
+    /// Whether the walk is over, so that no more tasks will come.
+    closed: bool,
+}
+
+struct Device {
+    dev: Option<u64>,
+    tasks: VecDeque<Task>,
+    /// Workers currently shredding a file on this device.
+    active: usize,
+}
+
+impl Queues {
+    /// Queues the next task. Directories are kept apart; other entries go to the queue of the
+    /// device they are on, and failures get a queue of their own.
+    fn push(&mut self, step: Step) {
+        let task = Task {
+            index: self.count,
+            path: step.path().to_path_buf(),
+            step,
+        };
+        self.unfinished.insert(self.count);
+        self.count += 1;
+
+        if let Step::RemoveDir(_) = task.step {
+            self.dirs.push_back(task);
+            return;
+        }
+
+        let dev = task.step.entry().map(|entry| entry.stat.dev());
+        let pos = match self.devices.iter().position(|d| d.dev == dev) {
+            Some(pos) => pos,
+            None => {
+                self.devices.push(Device {
+                    dev,
+                    tasks: VecDeque::new(),
+                    active: 0,
+                });
+                self.devices.len() - 1
+            }
+        };
+        self.devices[pos].tasks.push_back(task);
+    }
+
+    /// Takes the next task: a directory once everything before it is done, or else the next task
+    /// of the least busy device that has work left, preferring the device whose next task comes
+    /// first. A device whose next task removes a name of a file that is being overwritten waits
+    /// for that to finish. Returns the position of the device along with the task; `None` if
+    /// nothing can be started now.
+    fn pop(&mut self) -> Option<(Option<usize>, Task)> {
+        let first = self.unfinished.iter().next().cloned();
+        if self.dirs.front().is_some_and(|dir| Some(dir.index) == first) {
+            return self.dirs.pop_front().map(|dir| (None, dir));
+        }
+
+        let overwriting = &self.overwriting;
+        let pos = self.devices
+            .iter()
+            .enumerate()
+            .filter_map(|(i, d)| d.tasks.front().map(|task| (d.active, task, i)))
+            .filter(|&(_, task, _)| match task.step {
+                Step::Unlink(ref entry) => !overwriting.contains(&entry.stat.id()),
+                _ => true,
+            })
+            .map(|(active, task, i)| (active, task.index, i))
+            .min()
+            .map(|(_, _, i)| i)?;
+
+        let device = &mut self.devices[pos];
+        device.active += 1;
+        let task = device.tasks.pop_front()?;
+        if let Step::Shred(ref entry) | Step::Overwrite(ref entry) = task.step {
+            self.overwriting.insert(entry.stat.id());
+        }
+        Some((Some(pos), task))
+    }
+
+    /// Marks the task `index` taken with `pop` as done. `id` is the file it overwrote, if any.
+    fn done(&mut self, device: Option<usize>, index: usize, id: Option<(u64, u64)>) {
+        if let Some(device) = device {
+            self.devices[device].active -= 1;
+        }
+        self.unfinished.remove(&index);
+        if let Some(id) = id {
+            self.overwriting.remove(&id);
+        }
+    }
+
+    /// Whether all tasks have been taken and no more will come.
+    fn is_finished(&self) -> bool {
+        self.closed && self.dirs.is_empty() && self.devices.iter().all(|d| d.tasks.is_empty())
+    }
+}
+
+/// Shreds all `paths` with `config.jobs` worker threads. Errors are reported as they are printed;
+/// `ShremError::Incomplete` is returned if any entry failed.
+///
+/// Without `config.force`, tasks after a failed one are not started, while those before it still
+/// run, so that the same files are left over as in a sequential run (apart from tasks that were
+/// already running when the failure happened).
+pub(crate) fn shred_all(paths: &[&Path], config: &Config) -> Result<(), ShremError> {
+    // Each entry may hold a directory of its own open, and the rest is left for the files being
+    // shredded and the directories being walked.
+    let queued = (raise_open_files_limit() / 4).clamp(1, QUEUED);
+
+    let queues = Mutex::new(Queues {
+        devices: Vec::new(),
+        dirs: VecDeque::new(),
+        count: 0,
+        unfinished: BTreeSet::new(),
+        overwriting: HashSet::new(),
+        closed: false,
+    });
+    // Signalled whenever a task is queued or done, and when the walk is over.
+    let ready = Condvar::new();
+    let limit = AtomicUsize::new(usize::MAX);
+    let (tx, rx) = mpsc::channel::<(usize, bool, Vec<Line>)>();
+
+    let failed = thread::scope(|scope| {
+        for _ in 0..config.jobs {
+            let tx = tx.clone();
+            let queues = &queues;
+            let ready = &ready;
+            let limit = &limit;
+            scope.spawn(move || work(queues, ready, limit, tx, config));
+        }
+        drop(tx);
+        let printer = scope.spawn(move || print(rx));
+
+        'paths: for &path in paths {
+            for step in Plan::new(path, config) {
+                let mut guard = queues.lock().unwrap();
+                while guard.unfinished.len() >= queued {
+                    guard = ready.wait(guard).unwrap();
+                }
+                if guard.count > limit.load(Ordering::SeqCst) || is_interrupted() {
+                    break 'paths;
+                }
+                guard.push(step);
+                ready.notify_all();
+            }
+        }
+        queues.lock().unwrap().closed = true;
+        ready.notify_all();
+
+        printer.join().unwrap()
+    });
+
+    check_interrupt()?;
+    if failed > 0 {
+        Err(ShremError::Incomplete(failed))
+    } else {
+        Ok(())
+    }
+}
+
+/// Runs tasks until there are none left, sending the output of each to `tx`.
+fn work(queues: &Mutex<Queues>,
+        ready: &Condvar,
+        limit: &AtomicUsize,
+        tx: mpsc::Sender<(usize, bool, Vec<Line>)>,
+        config: &Config) {
+    loop {
+        let next = {
+            let mut queues = queues.lock().unwrap();
+            loop {
+                if let Some(next) = queues.pop() {
+                    break Some(next);
+                } else if queues.is_finished() {
+                    break None;
+                }
+                queues = ready.wait(queues).unwrap();
+            }
+        };
+        let (device, Task { index, path, step }) = match next {
+            Some(next) => next,
+            None => break,
+        };
+        let overwritten = match step {
+            Step::Shred(ref entry) | Step::Overwrite(ref entry) => Some(entry.stat.id()),
+            _ => None,
+        };
+
+        if index > limit.load(Ordering::SeqCst) || is_interrupted() {
+            queues.lock().unwrap().done(device, index, overwritten);
+            ready.notify_all();
+            continue;
+        }
+
+        let (ok, lines) = event::capture(|| {
+            match run_step(step, config) {
+                Ok(()) => true,
+                Err(e) => {
+                    report_error(&path, &e, config);
+                    false
+                }
+            }
+        });
+
+        if !ok && !config.force {
+            limit.fetch_min(index, Ordering::SeqCst);
+        }
+        queues.lock().unwrap().done(device, index, overwritten);
+        ready.notify_all();
+        if tx.send((index, ok, lines)).is_err() {
+            break;
+        }
+    }
+}
+
+/// Prints the output of tasks in index order as it comes in. Tasks skipped after a failure leave
+/// gaps, so whatever is still held back at the end is printed in order too. Returns the number of
+/// failed tasks.
+fn print(rx: mpsc::Receiver<(usize, bool, Vec<Line>)>) -> usize {
+    let mut pending = BTreeMap::new();
+    let mut next = 0;
+    let mut failed = 0;
+    for (index, ok, lines) in rx {
+        if !ok {
+            failed += 1;
+        }
+        pending.insert(index, lines);
+        while let Some(lines) = pending.remove(&next) {
+            for line in lines {
+                line.print();
+            }
+            next += 1;
+        }
+    }
+    for (_, lines) in pending {
+        for line in lines {
+            line.print();
+        }
+    }
+    failed
+}
+
+/// Raises the soft limit on open files to the hard limit, and returns the limit.
+fn raise_open_files_limit() -> usize {
+    unsafe {
+        let mut limit = std::mem::zeroed::<libc::rlimit>();
+        if libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) != 0 {
+            return usize::MAX;
+        }
+        if limit.rlim_cur < limit.rlim_max {
+            let soft = limit.rlim_cur;
+            limit.rlim_cur = limit.rlim_max;
+            if libc::setrlimit(libc::RLIMIT_NOFILE, &limit) != 0 {
+                limit.rlim_cur = soft;
+            }
+        }
+        limit.rlim_cur.min(usize::MAX as libc::rlim_t) as usize
+    }
+}
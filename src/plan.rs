@@ -0,0 +1,396 @@
+//! What shredding an argument amounts to: the entries to overwrite and remove, in the order to do
+//! it in, found by walking trees and following symlinks as `config.symlinks` says.
+//!
+//! A followed symlink stands for what it points to, which is shredded as if it had been named
+//! itself; the link is removed afterwards. Following a link to a directory that is being walked
+//! already is reported as a loop.
+//!
+//! Regular files with other hard links are handled as `config.hard_links` says, which may mean
+//! asking the user while planning. Each of them is overwritten at most once per argument.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use super::{not_found, prompt, Config, HardLinks, ShremError, Symlinks};
+use event::{self, Event};
+use at::{Entry, Visit, Walk};
+
+pub(crate) enum Step {
+    /// Overwrite a regular file and remove it.
+    Shred(Entry),
+    /// Overwrite a regular file and keep it: the target of a symlink that is not followed, with
+    /// `config.shred_link_targets`.
+    Overwrite(Entry),
+    /// Remove a symlink or special file without touching what it refers to.
+    Remove(Entry),
+    /// Remove a directory; everything in it has come before.
+    RemoveDir(Entry),
+    /// Hide and remove another name of a regular file that is overwritten under an earlier one.
+    Unlink(Entry),
+    /// Leave an entry alone: a regular file with other hard links.
+    Skip(Entry),
+    Failed(PathBuf, ShremError),
+}
+
+impl Step {
+    pub fn path(&self) -> &Path {
+        match *self {
+            Step::Shred(ref entry) |
+            Step::Overwrite(ref entry) |
+            Step::Remove(ref entry) |
+            Step::RemoveDir(ref entry) |
+            Step::Unlink(ref entry) |
+            Step::Skip(ref entry) => &entry.path,
+            Step::Failed(ref path, _) => path,
+        }
+    }
+
+    /// The entry the step works on, if it has one.
+    pub fn entry(&self) -> Option<&Entry> {
+        match *self {
+            Step::Shred(ref entry) |
+            Step::Overwrite(ref entry) |
+            Step::Remove(ref entry) |
+            Step::RemoveDir(ref entry) |
+            Step::Unlink(ref entry) |
+            Step::Skip(ref entry) => Some(entry),
+            Step::Failed(..) => None,
+        }
+    }
+}
+
+/// The steps for one argument, found lazily.
+pub(crate) struct Plan<'a> {
+    config: &'a Config,
+    steps: VecDeque<Step>,
+    /// Trees being walked, innermost last, each with the symlink that led to it, which is removed
+    /// once the walk is done.
+    walks: Vec<(Walk, Option<Entry>)>,
+    /// Directories being walked, to detect loops.
+    ancestors: HashSet<(u64, u64)>,
+    /// What was decided for each regular file with other hard links that has been found.
+    linked: HashMap<(u64, u64), HardLinks>,
+    /// Whether to decide about hard links without warning or asking.
+    quiet: bool,
+    single: bool,
+}
+
+impl<'a> Plan<'a> {
+    pub fn new(path: &Path, config: &'a Config) -> Plan<'a> {
+        Plan::with(path, config, false)
+    }
+
+    /// Like `new`, but without warning about hard links or asking about them, which counts as
+    /// yes. For looking ahead at what a run would do.
+    pub fn quiet(path: &Path, config: &'a Config) -> Plan<'a> {
+        Plan::with(path, config, true)
+    }
+
+    fn with(path: &Path, config: &'a Config, quiet: bool) -> Plan<'a> {
+        let mut plan = Plan {
+            config,
+            steps: VecDeque::new(),
+            walks: Vec::new(),
+            ancestors: HashSet::new(),
+            linked: HashMap::new(),
+            quiet,
+            single: false,
+        };
+
+        if config.recursive && config.preserve_root && path.is_absolute() &&
+           path.parent().is_none() {
+            plan.steps.push_back(Step::Failed(path.to_path_buf(), ShremError::PreservedRootError));
+        } else {
+            match Entry::of(path) {
+                Ok(entry) => plan.add(entry, config.symlinks != Symlinks::Never),
+                Err(e) => {
+                    plan.steps.push_back(Step::Failed(path.to_path_buf(), not_found(e, path)))
+                }
+            }
+        }
+
+        plan.single = plan.walks.is_empty() && plan.steps.len() == 1;
+        plan
+    }
+
+    /// Whether the argument is a single entry, rather than a tree or a symlink that stands for
+    /// more than one step.
+    pub fn is_single(&self) -> bool {
+        self.single
+    }
+
+    /// Adds the steps for `entry`, following it with `follow` if it is a symlink.
+    fn add(&mut self, entry: Entry, follow: bool) {
+        if entry.stat.is_symlink() {
+            self.add_symlink(entry, follow);
+        } else if entry.stat.is_dir() && self.config.recursive {
+            self.walks.push((Walk::new(entry), None));
+        } else if entry.stat.is_file() && self.is_linked(&entry) {
+            self.add_linked(entry, true);
+        } else if entry.stat.is_file() || entry.stat.is_dir() {
+            // A directory without `recursive` fails as it should when it is shredded.
+            self.steps.push_back(Step::Shred(entry));
+        } else {
+            self.steps.push_back(Step::Remove(entry));
+        }
+    }
+
+    fn add_symlink(&mut self, link: Entry, follow: bool) {
+        let target = match link.follow() {
+            Ok(target) => Some(target),
+            // Nothing to follow; the link is only removed.
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) if follow => {
+                self.steps.push_back(Step::Failed(link.path.clone(), e.into()));
+                return;
+            }
+            Err(_) => None,
+        };
+
+        match target {
+            Some(target) if follow => {
+                if target.stat.is_dir() && self.config.recursive {
+                    if self.ancestors.contains(&target.stat.id()) {
+                        let path = link.path;
+                        self.steps.push_back(Step::Failed(path.clone(), ShremError::Loop(path)));
+                    } else {
+                        self.walks.push((Walk::new(target), Some(link)));
+                    }
+                    return;
+                }
+                self.add(target, false);
+            }
+            Some(ref target) if self.config.shred_link_targets && target.stat.is_file() => {
+                if self.is_linked(target) {
+                    self.add_linked(target.clone(), false);
+                } else {
+                    self.steps.push_back(Step::Overwrite(target.clone()));
+                }
+            }
+            _ => {}
+        }
+        self.steps.push_back(Step::Remove(link));
+    }
+
+    /// Whether the regular file `entry` has other hard links, or had when another of its names was
+    /// found. Steps run as the plan is walked, so shredding that name may have removed it by now.
+    fn is_linked(&self, entry: &Entry) -> bool {
+        entry.stat.links() > 1 || self.linked.contains_key(&entry.stat.id())
+    }
+
+    /// Adds the step for a regular file with other hard links, which is removed afterwards with
+    /// `remove`. The first of its names decides; the later ones are only removed if it was
+    /// shredded, and skipped otherwise.
+    fn add_linked(&mut self, entry: Entry, remove: bool) {
+        let id = entry.stat.id();
+        let step = match self.linked.get(&id) {
+            Some(&HardLinks::ShredAll) if remove => Step::Unlink(entry),
+            Some(_) => Step::Skip(entry),
+            None => {
+                let decision = match self.decide(&entry) {
+                    Ok(decision) => decision,
+                    Err(e) => {
+                        self.steps.push_back(Step::Failed(entry.path.clone(), e.into()));
+                        return;
+                    }
+                };
+                self.linked.insert(id, decision);
+                match decision {
+                    HardLinks::ShredAll if remove => Step::Shred(entry),
+                    HardLinks::ShredAll | HardLinks::OverwriteOnly => Step::Overwrite(entry),
+                    _ => Step::Skip(entry),
+                }
+            }
+        };
+        self.steps.push_back(step);
+    }
+
+    /// Warns about a regular file with other hard links and, with `HardLinks::Ask`, asks whether
+    /// to shred it anyway. Returns what to do with it.
+    fn decide(&self, entry: &Entry) -> io::Result<HardLinks> {
+        if self.quiet {
+            return Ok(match self.config.hard_links {
+                HardLinks::Ask => HardLinks::ShredAll,
+                other => other,
+            });
+        }
+
+        let path = entry.path.as_path();
+        event::emit(self.config, Event::HardLinked { path, links: entry.stat.links() });
+        match self.config.hard_links {
+            HardLinks::Ask => {
+                if prompt(format_args!("shred '{}' anyway?", path.display()))? {
+                    Ok(HardLinks::ShredAll)
+                } else {
+                    Ok(HardLinks::Skip)
+                }
+            }
+            other => Ok(other),
+        }
+    }
+}
+
+impl<'a> Iterator for Plan<'a> {
+    type Item = Step;
+
+    fn next(&mut self) -> Option<Step> {
+        loop {
+            if let Some(step) = self.steps.pop_front() {
+                return Some(step);
+            }
+
+            let visit = match self.walks.last_mut() {
+                Some(&mut (ref mut walk, _)) => walk.next(),
+                None => return None,
+            };
+            match visit {
+                Some(Visit::Entry(entry)) => {
+                    if entry.stat.is_dir() {
+                        self.ancestors.insert(entry.stat.id());
+                    } else {
+                        let follow = self.config.symlinks == Symlinks::All;
+                        self.add(entry, follow);
+                    }
+                }
+                Some(Visit::Leave(entry)) => {
+                    self.ancestors.remove(&entry.stat.id());
+                    return Some(Step::RemoveDir(entry));
+                }
+                Some(Visit::Error(path, e)) => return Some(Step::Failed(path, e.into())),
+                None => {
+                    if let Some((_, Some(link))) = self.walks.pop() {
+                        return Some(Step::Remove(link));
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::os::unix::fs::symlink;
+    use std::path::PathBuf;
+    use std::process;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use event::Event;
+    use {ShremError, Shredder, Symlinks};
+
+    /// A tree with two names of one file, `a` and `b/c`.
+    fn linked_tree(name: &str) -> PathBuf {
+        let root = env::temp_dir().join(format!("shrem-test-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("b")).unwrap();
+        fs::write(root.join("a"), b"data").unwrap();
+        fs::hard_link(root.join("a"), root.join("b/c")).unwrap();
+        root
+    }
+
+    fn overwrites(root: &PathBuf, jobs: usize) -> usize {
+        let starts = Arc::new(AtomicUsize::new(0));
+        let shredder = {
+            let starts = starts.clone();
+            Shredder::new()
+                .passes(1)
+                .recursive(true)
+                .jobs(jobs)
+                .on_event(move |event| if let Event::Start { .. } = *event {
+                    starts.fetch_add(1, Ordering::SeqCst);
+                })
+        };
+        shredder.shred_all(&[root]).unwrap();
+        assert!(!root.exists());
+        starts.load(Ordering::SeqCst)
+    }
+
+    #[test]
+    fn hard_links_in_a_tree_are_overwritten_once() {
+        let root = linked_tree("links");
+        assert_eq!(overwrites(&root, 1), 1);
+    }
+
+    #[test]
+    fn hard_links_in_a_tree_are_overwritten_once_in_parallel() {
+        let root = linked_tree("links-parallel");
+        assert_eq!(overwrites(&root, 4), 1);
+    }
+
+    /// `tree/a`, with `tree/dir` linking to the directory `out` and `tree/file` to the file `t`,
+    /// which are outside of `tree`.
+    fn tree_with_links(name: &str) -> PathBuf {
+        let root = env::temp_dir().join(format!("shrem-test-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("tree")).unwrap();
+        fs::create_dir(root.join("out")).unwrap();
+        fs::write(root.join("tree/a"), b"data").unwrap();
+        fs::write(root.join("out/f"), b"data").unwrap();
+        fs::write(root.join("t"), b"data").unwrap();
+        symlink("../out", root.join("tree/dir")).unwrap();
+        symlink("../t", root.join("tree/file")).unwrap();
+        root
+    }
+
+    fn shredder(symlinks: Symlinks) -> Shredder {
+        Shredder::new().passes(1).zero(false).recursive(true).symlinks(symlinks)
+    }
+
+    #[test]
+    fn symlinks_in_a_tree_are_only_removed() {
+        let root = tree_with_links("symlinks-never");
+        shredder(Symlinks::Never).shred(root.join("tree")).unwrap();
+        assert!(!root.join("tree").exists());
+        assert_eq!(fs::read(root.join("out/f")).unwrap(), b"data");
+        assert_eq!(fs::read(root.join("t")).unwrap(), b"data");
+
+        // -H only follows the arguments.
+        let root = tree_with_links("symlinks-command-line");
+        shredder(Symlinks::CommandLine).shred(root.join("tree")).unwrap();
+        assert!(!root.join("tree").exists());
+        assert_eq!(fs::read(root.join("t")).unwrap(), b"data");
+        symlink("out", root.join("link")).unwrap();
+        shredder(Symlinks::CommandLine).shred(root.join("link")).unwrap();
+        assert!(!root.join("link").exists());
+        assert!(!root.join("out").exists());
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn symlinks_in_a_tree_are_followed_with_all() {
+        let root = tree_with_links("symlinks-all");
+        shredder(Symlinks::All).shred(root.join("tree")).unwrap();
+        assert!(!root.join("tree").exists());
+        assert!(!root.join("out").exists());
+        assert!(!root.join("t").exists());
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn link_targets_are_overwritten_but_kept() {
+        let root = tree_with_links("symlinks-targets");
+        shredder(Symlinks::Never).shred_link_targets(true).shred(root.join("tree")).unwrap();
+        assert!(!root.join("tree").exists());
+        let target = fs::read(root.join("t")).unwrap();
+        assert_eq!(target.len(), 4);
+        assert_ne!(target, b"data");
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn loops_are_left_in_place() {
+        let root = tree_with_links("symlinks-loop");
+        symlink(".", root.join("tree/loop")).unwrap();
+        // The loop, and the directory it keeps from being removed.
+        match shredder(Symlinks::All).force(true).shred(root.join("tree")) {
+            Err(ShremError::Incomplete(2)) => {}
+            result => panic!("{:?}", result),
+        }
+        assert_eq!(fs::read_link(root.join("tree/loop")).unwrap(), PathBuf::from("."));
+        assert!(!root.join("tree/a").exists());
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
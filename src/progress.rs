@@ -0,0 +1,301 @@
+//! Progress reporting driven by the library's events.
+//!
+//! `--progress` shows a status line on stderr. The total is computed before shredding starts, so
+//! that the line can show how much of the whole job is done. Bytes are counted once per pass, so a
+//! 1 GB file overwritten four times contributes 4 GB. On a terminal the line is redrawn in place;
+//! otherwise a plain line is printed every few seconds, which keeps log files readable.
+//!
+//! `--progress-fd` writes records for other programs to a file descriptor instead.
+
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::io::{FromRawFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use shrem::event::{format_bytes, format_duration, Event};
+use shrem::method;
+use shrem::{Config, HardLinks};
+
+/// Minimum time between redraws on a terminal.
+const REDRAW: Duration = Duration::from_millis(100);
+/// Time between lines when stderr is not a terminal.
+const PERIOD: Duration = Duration::from_secs(10);
+
+pub struct Progress {
+    state: Mutex<State>,
+    /// Whether stderr is a terminal.
+    tty: bool,
+    /// Whether `-v` lines are printed, which have to be kept apart from the status line.
+    verbose: bool,
+    passes: u64,
+    /// Whether files with other hard links are left out of the total (`--hardlinks=skip`).
+    skip_linked: bool,
+}
+
+struct State {
+    start: Instant,
+    /// Bytes to write in total, over all passes.
+    total: u64,
+    /// Bytes written so far.
+    done: u64,
+    files: HashMap<PathBuf, Shredding>,
+    /// Files with other hard links that are counted in the total under this name. Their other
+    /// names are not.
+    linked: HashSet<PathBuf>,
+    /// The file, pass and pass count shown.
+    current: Option<(PathBuf, usize, usize)>,
+    last_draw: Option<Instant>,
+    /// Whether the status line is currently on the terminal.
+    drawn: bool,
+}
+
+/// A file that is being shredded.
+struct Shredding {
+    size: u64,
+    /// Bytes of this file already added to `State::done`.
+    counted: u64,
+}
+
+impl Progress {
+    /// Computes the total size of `paths`. The display is updated by passing it every event.
+    pub fn new(paths: &[&Path], config: &Config) -> Progress {
+        let passes = method::passes(config).len() as u64;
+        Progress {
+            state: Mutex::new(State {
+                start: Instant::now(),
+                total: shrem::total_bytes(paths, config) * passes,
+                done: 0,
+                files: HashMap::new(),
+                linked: HashSet::new(),
+                current: None,
+                last_draw: None,
+                drawn: false,
+            }),
+            tty: unsafe { libc::isatty(libc::STDERR_FILENO) } == 1,
+            verbose: config.verbose,
+            passes,
+            skip_linked: config.hard_links == HardLinks::Skip,
+        }
+    }
+
+    pub fn update(&self, event: &Event) {
+        let mut state = self.state.lock().unwrap();
+
+        match *event {
+            Event::Start { path, bytes } => {
+                state.files.insert(path.to_path_buf(), Shredding { size: bytes, counted: 0 });
+                state.current = Some((path.to_path_buf(), 0, self.passes as usize));
+            }
+            Event::PassStart { path, pass, total, .. } => {
+                state.current = Some((path.to_path_buf(), pass, total));
+            }
+            Event::Progress { path, pass, bytes } => {
+                state.count(path, |size| (pass as u64 - 1) * size + bytes);
+            }
+            Event::PassDone { path, pass, .. } => {
+                state.count(path, |size| pass as u64 * size);
+            }
+            Event::Done { path, .. } => {
+                // External backends don't report passes; the whole file is counted here.
+                let passes = self.passes;
+                state.count(path, |size| passes * size);
+                state.files.remove(path);
+            }
+            Event::Error { path, .. } => {
+                // What was not written will not be, so it no longer belongs to the total.
+                if let Some(file) = state.files.remove(path) {
+                    let rest = (file.size * self.passes).saturating_sub(file.counted);
+                    state.total = state.total.saturating_sub(rest);
+                }
+            }
+            Event::HardLinked { path, .. } if !self.skip_linked => {
+                state.linked.insert(path.to_path_buf());
+            }
+            Event::Skipped { path } => {
+                let counted = state.linked.remove(path);
+                if let Ok(metadata) = fs::metadata(path) {
+                    if metadata.is_file() && (metadata.nlink() == 1 || counted) {
+                        state.total = state.total.saturating_sub(metadata.len() * self.passes);
+                    }
+                }
+            }
+            _ => {}
+        }
+
+        let printed = match *event {
+            Event::Error { .. } => true,
+            Event::Progress { .. } => false,
+            _ => self.verbose,
+        };
+
+        if self.tty && printed {
+            // Make room for the line that is about to be printed; the next event redraws.
+            if state.drawn {
+                eprint!("\r\x1b[K");
+                state.drawn = false;
+            }
+            state.last_draw = None;
+        } else {
+            self.draw(&mut state, false);
+        }
+    }
+
+    /// Prints the final state of the status line.
+    pub fn finish(&self) {
+        let mut state = self.state.lock().unwrap();
+        self.draw(&mut state, true);
+        if self.tty {
+            eprintln!();
+        }
+    }
+
+    fn draw(&self, state: &mut State, force: bool) {
+        let now = Instant::now();
+        let interval = if self.tty { REDRAW } else { PERIOD };
+        let due = match state.last_draw {
+            Some(last) => now.duration_since(last) >= interval,
+            // The first plain line is only printed after a period, so short runs stay quiet.
+            None => self.tty || now.duration_since(state.start) >= PERIOD,
+        };
+        if !force && !due {
+            return;
+        }
+        state.last_draw = Some(now);
+
+        let line = state.line(now);
+        if self.tty {
+            let width = terminal_width();
+            let line = line.chars().take(width.saturating_sub(1)).collect::<String>();
+            eprint!("\r\x1b[K{}", line);
+            state.drawn = true;
+        } else {
+            eprintln!("{}", line);
+        }
+    }
+}
+
+impl State {
+    /// Adds what has been written to `path` since the last call. `counted` maps the file size to
+    /// the number of bytes written in total.
+    fn count<F>(&mut self, path: &Path, counted: F)
+        where F: FnOnce(u64) -> u64
+    {
+        if let Some(file) = self.files.get_mut(path) {
+            let counted = counted(file.size);
+            if counted > file.counted {
+                self.done += counted - file.counted;
+                file.counted = counted;
+            }
+        }
+    }
+
+    fn line(&self, now: Instant) -> String {
+        let elapsed = now.duration_since(self.start).as_secs_f64();
+        let rate = if elapsed > 0.0 { self.done as f64 / elapsed } else { 0.0 };
+        let eta = if rate > 0.0 {
+            format_duration(Duration::from_secs_f64(self.total.saturating_sub(self.done) as f64 /
+                                                    rate))
+        } else {
+            String::from("-:--:--")
+        };
+
+        let file = match self.current {
+            Some((ref path, 0, _)) => format!("{}: ", path.display()),
+            Some((ref path, pass, total)) => format!("{}: pass {}/{}, ", path.display(), pass, total),
+            None => String::new(),
+        };
+
+        format!("shrem: {}{} / {}, {:.1} MB/s, ETA {}",
+                file,
+                format_bytes(self.done),
+                format_bytes(self.total),
+                rate / 1_000_000.0,
+                eta)
+    }
+}
+
+/// `--progress-fd`: one line per update, `INDEX PASS BYTES TOTAL`, where `INDEX` counts the files
+/// from 1 in the order they are started, `BYTES` is how much of the current pass has been written
+/// and `TOTAL` is the size of the file. Pass 0 means the file has been started.
+pub struct Records {
+    state: Mutex<RecordState>,
+    passes: usize,
+}
+
+struct RecordState {
+    out: File,
+    /// Index, size and last record of the files being shredded.
+    files: HashMap<PathBuf, (usize, u64, (usize, u64))>,
+    count: usize,
+}
+
+impl Records {
+    /// Writes to the already open descriptor `fd`, which is not passed on to backend processes.
+    pub fn open(fd: RawFd, config: &Config) -> io::Result<Records> {
+        if unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Records {
+            state: Mutex::new(RecordState {
+                out: unsafe { File::from_raw_fd(fd) },
+                files: HashMap::new(),
+                count: 0,
+            }),
+            passes: method::passes(config).len(),
+        })
+    }
+
+    pub fn update(&self, event: &Event) {
+        let mut state = self.state.lock().unwrap();
+
+        let (path, pass, bytes) = match *event {
+            Event::Start { path, bytes } => {
+                state.count += 1;
+                let index = state.count;
+                state.files.insert(path.to_path_buf(), (index, bytes, (0, 0)));
+                (path, 0, 0)
+            }
+            Event::PassStart { path, pass, .. } => (path, pass, 0),
+            Event::Progress { path, pass, bytes } => (path, pass, bytes),
+            Event::PassDone { path, pass, bytes, .. } => (path, pass, bytes),
+            // External backends don't report passes; their files are finished here.
+            Event::Done { path, bytes, .. } => (path, self.passes, bytes),
+            _ => return,
+        };
+
+        let record = match state.files.get_mut(path) {
+            // Only changes are written; `Done` usually repeats the last `PassDone`.
+            Some(&mut (_, _, last)) if last == (pass, bytes) && pass > 0 => None,
+            Some(&mut (index, size, ref mut last)) => {
+                *last = (pass, bytes);
+                Some(format!("{} {} {} {}\n", index, pass, bytes, size))
+            }
+            None => None,
+        };
+
+        if let Event::Done { path, .. } = *event {
+            state.files.remove(path);
+        }
+
+        if let Some(record) = record {
+            // The reader going away must not stop the shredding.
+            let _ = state.out.write_all(record.as_bytes());
+        }
+    }
+}
+
+/// Columns of the terminal on stderr, or 80 if unknown.
+fn terminal_width() -> usize {
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    let ret = unsafe { libc::ioctl(libc::STDERR_FILENO, libc::TIOCGWINSZ, &mut size) };
+    if ret == 0 && size.ws_col > 0 {
+        size.ws_col as usize
+    } else {
+        80
+    }
+}
@@ -0,0 +1,465 @@
+//! Certificates of destruction, written with `--report`.
+
+use std::env;
+use std::ffi::CStr;
+use std::fmt::Write as FmtWrite;
+use std::fs::{self, File, Metadata};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::{Config, ShremError};
+use at::Entry;
+use event::Json;
+use extents::Placement;
+use sha256::{self, Sha256};
+
+/// Format of the report file, selected with `--report-format`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ReportFormat {
+    Json,
+    Text,
+}
+
+impl ReportFormat {
+    pub fn from_name(name: &str) -> Option<ReportFormat> {
+        match name {
+            "json" => Some(ReportFormat::Json),
+            "text" => Some(ReportFormat::Text),
+            _ => None,
+        }
+    }
+}
+
+/// What happened to one file.
+struct Record {
+    path: PathBuf,
+    /// Where the file is really stored, if that is somewhere else (the upper layer of an overlay).
+    backing: Option<PathBuf>,
+    /// Whether the file is on an overlay whose layers could not be looked at, so that it is not
+    /// known whether a lower one still has the data.
+    layer_unknown: bool,
+    size: u64,
+    dev: u64,
+    ino: u64,
+    mtime: i64,
+    sha256: Option<String>,
+    /// `None` if the backend chose the passes itself.
+    passes: Option<usize>,
+    verified: Option<bool>,
+    /// Whether `--forensic-verify` found none of the original data on the device.
+    forensic: Option<bool>,
+    /// Whether the data was overwritten in place, with `--check-extents`.
+    placement: Option<Placement>,
+    started: SystemTime,
+    finished: SystemTime,
+    /// Whether the file was left alone, e.g. at the interactive prompt.
+    skipped: bool,
+    error: Option<String>,
+}
+
+static RECORDS: Mutex<Vec<Record>> = Mutex::new(Vec::new());
+
+/// Facts about a file collected before it is overwritten.
+pub(crate) struct Before {
+    /// Absolute path, resolved while the original name still exists.
+    path: PathBuf,
+    backing: Option<PathBuf>,
+    layer_unknown: bool,
+    metadata: Metadata,
+    sha256: Option<String>,
+    started: SystemTime,
+}
+
+/// Collects what has to be known about `path`, opened as `file` and stored at `backing` (or in
+/// an unknown layer of an overlay, with `layer_unknown`), before overwriting destroys it. `file`
+/// must have been opened for reading if `config.report_hash` is set.
+pub(crate) fn before(path: &Path,
+                     file: &File,
+                     backing: Option<&PathBuf>,
+                     layer_unknown: bool,
+                     config: &Config)
+                     -> Result<Before, ShremError> {
+    let metadata = file.metadata()?;
+    let sha256 = if config.report_hash {
+        Some(hash_file(file)?)
+    } else {
+        None
+    };
+
+    Ok(Before {
+        path: absolute(path),
+        backing: backing.cloned(),
+        layer_unknown,
+        metadata,
+        sha256,
+        started: SystemTime::now(),
+    })
+}
+
+/// Adds the outcome of shredding the file described by `before` to the report.
+pub(crate) fn record(before: Before,
+                     result: &Result<Option<Placement>, ShremError>,
+                     config: &Config) {
+    let verified = match *result {
+        _ if !config.verify => None,
+        Ok(_) => Some(true),
+        Err(ShremError::VerificationFailed(_)) => Some(false),
+        Err(_) => None,
+    };
+    let forensic = match *result {
+        _ if !config.forensic_verify => None,
+        Ok(_) => Some(true),
+        Err(ShremError::DataRemains(_)) => Some(false),
+        Err(_) => None,
+    };
+
+    RECORDS.lock().unwrap().push(Record {
+        path: before.path,
+        backing: before.backing,
+        layer_unknown: before.layer_unknown,
+        size: before.metadata.len(),
+        dev: before.metadata.dev(),
+        ino: before.metadata.ino(),
+        mtime: before.metadata.mtime(),
+        sha256: before.sha256,
+        passes: config.backend.backend().passes(config),
+        verified,
+        forensic,
+        placement: result.as_ref().ok().and_then(|&placement| placement),
+        started: before.started,
+        finished: SystemTime::now(),
+        skipped: false,
+        error: result.as_ref().err().map(|e| e.to_string()),
+    });
+}
+
+/// Adds a file that was not overwritten to the report: it was skipped, or `error` stopped it
+/// before anything was written.
+pub(crate) fn not_shredded(entry: &Entry, error: Option<&ShremError>) {
+    let (dev, ino) = entry.stat.id();
+    let now = SystemTime::now();
+    RECORDS.lock().unwrap().push(Record {
+        path: absolute(&entry.path),
+        backing: None,
+        layer_unknown: false,
+        size: entry.stat.size(),
+        dev,
+        ino,
+        mtime: entry.stat.mtime(),
+        sha256: None,
+        passes: Some(0),
+        verified: None,
+        forensic: None,
+        placement: None,
+        started: now,
+        finished: now,
+        skipped: error.is_none(),
+        error: error.map(|e| e.to_string()),
+    });
+}
+
+/// `path` resolved while the original name still exists. Paths longer than `PATH_MAX` cannot be
+/// resolved; they are made absolute at least.
+fn absolute(path: &Path) -> PathBuf {
+    fs::canonicalize(path)
+        .or_else(|_| env::current_dir().map(|dir| dir.join(path)))
+        .unwrap_or_else(|_| path.to_path_buf())
+}
+
+fn hash_file(mut file: &File) -> io::Result<String> {
+    file.seek(SeekFrom::Start(0))?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(sha256::to_hex(&hasher.finish()))
+}
+
+/// A file name for a report written to `--report-dir`, unique per second and process.
+pub fn file_name(format: ReportFormat) -> String {
+    let stamp = system_timestamp(SystemTime::now()).replace(['-', ':'], "");
+    let ext = match format {
+        ReportFormat::Json => "json",
+        ReportFormat::Text => "txt",
+    };
+    format!("shrem-{}-{}.{}", stamp, process::id(), ext)
+}
+
+/// Writes the collected records to `path`.
+pub fn write(path: &Path, format: ReportFormat, config: &Config) -> io::Result<()> {
+    let records = RECORDS.lock().unwrap();
+    let contents = match format {
+        ReportFormat::Json => to_json(&records, config),
+        ReportFormat::Text => to_text(&records, config),
+    };
+    File::create(path)?.write_all(contents.as_bytes())
+}
+
+/// Signs the report at `path` with the Ed25519 private key in `key` (PEM), writing the detached
+/// signature next to it as `<path>.sig`. OpenSSL does the signing.
+pub fn sign(path: &Path, key: &Path) -> Result<PathBuf, ShremError> {
+    let mut sig = path.as_os_str().to_owned();
+    sig.push(".sig");
+    let sig = PathBuf::from(sig);
+
+    let status = Command::new("openssl")
+        .args(["pkeyutl", "-sign", "-rawin", "-inkey"])
+        .arg(key)
+        .arg("-in")
+        .arg(path)
+        .arg("-out")
+        .arg(&sig)
+        .status()?;
+    if !status.success() {
+        return Err(ShremError::ExternalProcessError(status));
+    }
+
+    Ok(sig)
+}
+
+fn method_name(config: &Config) -> String {
+    match config.method {
+        Some(method) => method.description.to_owned(),
+        None => config.backend.name().to_owned(),
+    }
+}
+
+fn verification(record: &Record) -> &'static str {
+    match record.verified {
+        None => "not performed",
+        // What was read back may be a copy in the upper layer.
+        Some(true) if record.layer_unknown => "inconclusive (layer unknown)",
+        Some(true) => "passed",
+        Some(false) => "failed",
+    }
+}
+
+fn forensic(record: &Record) -> &'static str {
+    match record.forensic {
+        None => "not performed",
+        Some(true) => "passed",
+        Some(false) => "failed",
+    }
+}
+
+fn placement(record: &Record) -> &'static str {
+    match record.placement {
+        None => "not checked",
+        Some(Placement::InPlace) => "in place",
+        Some(Placement::Relocated) => "relocated",
+    }
+}
+
+fn to_json(records: &[Record], config: &Config) -> String {
+    let mut entries = String::from("[");
+    for (i, record) in records.iter().enumerate() {
+        if i > 0 {
+            entries.push(',');
+        }
+
+        let mut json = Json::new();
+        json.path("path", &record.path);
+        if let Some(ref backing) = record.backing {
+            json.path("backing_path", backing);
+        }
+        if record.layer_unknown {
+            json.str("layer", "unknown");
+        }
+        json.num("size", record.size)
+            .num("device", record.dev)
+            .num("inode", record.ino)
+            .str("mtime", &timestamp(record.mtime));
+        if let Some(ref digest) = record.sha256 {
+            json.str("sha256", digest);
+        }
+        match record.passes {
+            Some(passes) => json.num("passes", passes as u64),
+            None => json.raw("passes", "null"),
+        };
+        json.str("verification", verification(record))
+            .str("extents", placement(record))
+            .str("forensic_verification", forensic(record))
+            .str("started", &system_timestamp(record.started))
+            .str("finished", &system_timestamp(record.finished));
+        match record.error {
+            Some(ref e) => json.str("result", "error").str("error", e),
+            None if record.skipped => json.str("result", "skipped"),
+            None if record.layer_unknown => json.str("result", "overwritten"),
+            None => json.str("result", "destroyed"),
+        };
+        entries.push_str(&json.finish());
+    }
+    entries.push(']');
+
+    let mut json = Json::new();
+    json.str("created", &system_timestamp(SystemTime::now()))
+        .str("hostname", &hostname())
+        .str("user", &user())
+        .str("backend", config.backend.name())
+        .str("method", &method_name(config))
+        .raw("entries", &entries);
+
+    let mut s = json.finish();
+    s.push('\n');
+    s
+}
+
+fn to_text(records: &[Record], config: &Config) -> String {
+    let mut s = String::new();
+
+    let _ = writeln!(s, "Certificate of destruction");
+    let _ = writeln!(s);
+    let _ = writeln!(s, "Created:  {}", system_timestamp(SystemTime::now()));
+    let _ = writeln!(s, "Hostname: {}", hostname());
+    let _ = writeln!(s, "User:     {}", user());
+    let _ = writeln!(s, "Backend:  {}", config.backend.name());
+    let _ = writeln!(s, "Method:   {}", method_name(config));
+
+    for record in records {
+        let _ = writeln!(s);
+        let _ = writeln!(s, "{}", record.path.display());
+        if let Some(ref backing) = record.backing {
+            let _ = writeln!(s, "  Stored at:    {}", backing.display());
+        }
+        if record.layer_unknown {
+            let _ = writeln!(s, "  Layer:        unknown");
+        }
+        let _ = writeln!(s, "  Size:         {} bytes", record.size);
+        let _ = writeln!(s, "  Device:       {}", record.dev);
+        let _ = writeln!(s, "  Inode:        {}", record.ino);
+        let _ = writeln!(s, "  Modified:     {}", timestamp(record.mtime));
+        if let Some(ref digest) = record.sha256 {
+            let _ = writeln!(s, "  SHA-256:      {}", digest);
+        }
+        let _ = match record.passes {
+            Some(passes) => writeln!(s, "  Passes:       {}", passes),
+            None => writeln!(s, "  Passes:       backend default"),
+        };
+        let _ = writeln!(s, "  Verification: {}", verification(record));
+        let _ = writeln!(s, "  Extents:      {}", placement(record));
+        let _ = writeln!(s, "  Forensic:     {}", forensic(record));
+        let _ = writeln!(s, "  Started:      {}", system_timestamp(record.started));
+        let _ = writeln!(s, "  Finished:     {}", system_timestamp(record.finished));
+        match record.error {
+            Some(ref e) => {
+                let _ = writeln!(s, "  Result:       error: {}", e);
+            }
+            None if record.skipped => {
+                let _ = writeln!(s, "  Result:       skipped");
+            }
+            None if record.layer_unknown => {
+                let _ = writeln!(s,
+                                 "  Result:       overwritten, but a lower layer may keep the \
+                                  data");
+            }
+            None => {
+                let _ = writeln!(s, "  Result:       destroyed");
+            }
+        }
+    }
+
+    s
+}
+
+fn hostname() -> String {
+    let mut buf = [0u8; 256];
+    let ret = unsafe { libc::gethostname(buf.as_mut_ptr() as *mut libc::c_char, buf.len()) };
+    if ret != 0 {
+        return String::from("unknown");
+    }
+    let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    String::from_utf8_lossy(&buf[..len]).into_owned()
+}
+
+/// The real user, as `name (uid)`.
+fn user() -> String {
+    let uid = unsafe { libc::getuid() };
+
+    let mut pwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut buf = vec![0 as libc::c_char; 1024];
+    let mut result = std::ptr::null_mut();
+    let ret = unsafe { libc::getpwuid_r(uid, &mut pwd, buf.as_mut_ptr(), buf.len(), &mut result) };
+
+    if ret == 0 && !result.is_null() {
+        let name = unsafe { CStr::from_ptr(pwd.pw_name) };
+        format!("{} ({})", name.to_string_lossy(), uid)
+    } else {
+        format!("{}", uid)
+    }
+}
+
+fn system_timestamp(time: SystemTime) -> String {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(d) => timestamp(d.as_secs() as i64),
+        Err(e) => timestamp(-(e.duration().as_secs() as i64)),
+    }
+}
+
+/// Formats seconds since the Unix epoch as an RFC 3339 UTC timestamp.
+fn timestamp(secs: i64) -> String {
+    let days = secs.div_euclid(86400);
+    let rem = secs.rem_euclid(86400);
+
+    // Howard Hinnant's civil_from_days.
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+            year,
+            month,
+            day,
+            rem / 3600,
+            rem % 3600 / 60,
+            rem % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    use super::{write, ReportFormat};
+    use {shred_file, Config};
+
+    #[test]
+    fn files_that_fail_early_are_reported() {
+        let root = env::temp_dir().join(format!("shrem-test-report-{}", process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("dir")).unwrap();
+
+        let config = Config {
+            report: true,
+            ..Config::default()
+        };
+        assert!(shred_file(root.join("dir"), &config).is_err());
+
+        let report = root.join("report.json");
+        write(&report, ReportFormat::Json, &config).unwrap();
+        let report = fs::read_to_string(&report).unwrap();
+        assert!(report.contains(&format!("\"path\":\"{}\"", root.join("dir").display())),
+                "{}",
+                report);
+        assert!(report.contains("\"passes\":0"), "{}", report);
+        assert!(report.contains("\"result\":\"error\",\"error\":\"Is a directory\""),
+                "{}",
+                report);
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,345 @@
+//! Option defaults from configuration files, profiles and `SHREM_OPTS`.
+//!
+//! Settings are written as long option names, e.g.
+//!
+//! ```toml
+//! verbose = true
+//!
+//! [profile.paranoid]
+//! method = "gutmann"
+//! verify = true
+//! report-dir = "/var/log/shrem"
+//! ```
+//!
+//! On/off options have a negation: `--no-NAME`, or `--NAME` for those called `no-NAME`. Setting
+//! one to `false` stands for its negation, so that a profile can turn off what the top level turns
+//! on; on the command line the negation overrides a setting.
+//!
+//! Each source is turned into a command line and parsed by the same `App` as the real arguments.
+//! Looking an option up goes through the sources in order of precedence: the command line,
+//! `SHREM_OPTS`, the selected profile, and then the top-level settings. The user's file comes
+//! before `/etc/shrem.toml` at each level.
+
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use clap::{self, ArgMatches, Values};
+
+const SYSTEM_CONFIG: &str = "/etc/shrem.toml";
+
+/// Parsed argument sources, highest precedence first.
+pub struct Settings<'a> {
+    layers: Vec<ArgMatches<'a>>,
+}
+
+impl<'a> Settings<'a> {
+    /// Gathers all sources. `parse` turns a command line into matches; `cli` are the matches of
+    /// the real command line.
+    pub fn load<F>(cli: ArgMatches<'a>, parse: F) -> Result<Settings<'a>, String>
+        where F: Fn(Vec<String>) -> clap::Result<ArgMatches<'a>>
+    {
+        let parse_args = |origin: &str, args: Vec<String>| {
+            let mut argv = vec![String::from("shrem")];
+            argv.extend(args);
+            parse(argv).map_err(|e| {
+                // Only the first line; the usage that follows refers to the command line.
+                let message = e.message.lines().next().unwrap_or("");
+                format!("{}: {}", origin, message.trim_start_matches("error: "))
+            })
+        };
+
+        let mut layers = vec![cli];
+
+        if let Ok(opts) = env::var("SHREM_OPTS") {
+            let args = opts.split_whitespace().map(String::from).collect();
+            layers.push(parse_args("SHREM_OPTS", args)?);
+        }
+
+        let mut files = Vec::new();
+        for path in config_paths() {
+            match fs::read_to_string(&path) {
+                Ok(text) => {
+                    let tables = parse_toml(&text)
+                        .map_err(|e| format!("{}: {}", path.display(), e))?;
+                    files.push((path, tables));
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(format!("{}: {}", path.display(), e)),
+            }
+        }
+
+        let profile = layers.iter().filter_map(|m| m.value_of("profile")).next().map(String::from);
+        if let Some(profile) = profile {
+            let table = format!("profile.{}", profile);
+            let mut found = false;
+            for (path, tables) in &files {
+                if let Some(settings) = tables.iter().find(|t| t.name == table) {
+                    layers.push(parse_args(&path.display().to_string(), settings.to_args())?);
+                    found = true;
+                }
+            }
+            if !found {
+                return Err(format!("profile '{}' is not defined", profile));
+            }
+        }
+
+        for (path, tables) in &files {
+            if let Some(settings) = tables.iter().find(|t| t.name.is_empty()) {
+                layers.push(parse_args(&path.display().to_string(), settings.to_args())?);
+            }
+        }
+
+        Ok(Settings { layers })
+    }
+
+    /// Whether `name` is set by the highest-precedence source that sets it or its negation.
+    pub fn is_present(&self, name: &str) -> bool {
+        let negation = negation(name);
+        self.layers
+            .iter()
+            .find(|m| m.is_present(name) || m.is_present(&negation))
+            .is_some_and(|m| m.is_present(name))
+    }
+
+    pub fn value_of(&self, name: &str) -> Option<&str> {
+        self.layers.iter().filter_map(|m| m.value_of(name)).next()
+    }
+
+    /// Positional arguments are only taken from the real command line.
+    pub fn values_of(&self, name: &str) -> Option<Values<'_>> {
+        self.layers[0].values_of(name)
+    }
+
+    /// The matches of the real command line alone.
+    pub fn command_line(&self) -> &ArgMatches<'a> {
+        &self.layers[0]
+    }
+
+    /// The matches of the highest-precedence source that sets any of `names`. Used for options
+    /// that conflict with each other, so that e.g. `-n` on the command line overrides a `method`
+    /// from a profile instead of being combined with it.
+    pub fn first_setting(&self, names: &[&str]) -> Option<&ArgMatches<'a>> {
+        self.layers.iter().find(|m| names.iter().any(|name| m.is_present(name)))
+    }
+}
+
+/// `$XDG_CONFIG_HOME/shrem/config.toml` (or `~/.config/shrem/config.toml`) and then
+/// `/etc/shrem.toml`.
+fn config_paths() -> Vec<PathBuf> {
+    let mut paths = Vec::new();
+
+    let user_dir = env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")));
+    if let Some(dir) = user_dir {
+        paths.push(dir.join("shrem").join("config.toml"));
+    }
+
+    paths.push(PathBuf::from(SYSTEM_CONFIG));
+    paths
+}
+
+/// The option that turns `name` off: `no-NAME`, or `NAME` without its `no-`.
+fn negation(name: &str) -> String {
+    match name.strip_prefix("no-") {
+        Some(name) => name.to_owned(),
+        None => format!("no-{}", name),
+    }
+}
+
+enum Value {
+    Bool(bool),
+    Str(String),
+}
+
+/// The key/value pairs of one `[table]`; the top level has an empty name.
+struct Table {
+    name: String,
+    entries: Vec<(String, Value)>,
+}
+
+impl Table {
+    /// Turns the settings into long options. `passes` is accepted as an alias of `iterations`.
+    fn to_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        for (key, value) in &self.entries {
+            let key = if key == "passes" { "iterations" } else { key.as_str() };
+            match *value {
+                Value::Bool(true) => args.push(format!("--{}", key)),
+                Value::Bool(false) => args.push(format!("--{}", negation(key))),
+                Value::Str(ref s) => {
+                    args.push(format!("--{}", key));
+                    args.push(s.clone());
+                }
+            }
+        }
+        args
+    }
+}
+
+/// Parses the subset of TOML that configuration files need: tables, comments, and keys set to
+/// strings, integers or booleans.
+fn parse_toml(text: &str) -> Result<Vec<Table>, String> {
+    let top = Table {
+        name: String::new(),
+        entries: Vec::new(),
+    };
+    let mut tables = vec![top];
+
+    for (i, line) in text.lines().enumerate() {
+        let err = |msg: &str| format!("line {}: {}", i + 1, msg);
+        let line = strip_comment(line).trim();
+
+        if line.is_empty() {
+            continue;
+        }
+
+        if line.starts_with('[') {
+            if !line.ends_with(']') || line.starts_with("[[") {
+                return Err(err("invalid table header"));
+            }
+            let name = line[1..line.len() - 1].split('.').map(str::trim).collect::<Vec<_>>();
+            if name.iter().any(|part| part.is_empty()) {
+                return Err(err("invalid table header"));
+            }
+            tables.push(Table {
+                name: name.join("."),
+                entries: Vec::new(),
+            });
+            continue;
+        }
+
+        let eq = line.find('=').ok_or_else(|| err("expected `key = value`"))?;
+        let key = line[..eq].trim();
+        let raw = line[eq + 1..].trim();
+
+        if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+            return Err(err("invalid key"));
+        }
+
+        let value = if raw == "true" {
+            Value::Bool(true)
+        } else if raw == "false" {
+            Value::Bool(false)
+        } else if raw.len() >= 2 && raw.starts_with('\'') && raw.ends_with('\'') {
+            Value::Str(raw[1..raw.len() - 1].to_owned())
+        } else if raw.len() >= 2 && raw.starts_with('"') && raw.ends_with('"') {
+            Value::Str(unescape(&raw[1..raw.len() - 1]).ok_or_else(|| err("invalid string"))?)
+        } else if !raw.is_empty() && raw.chars().all(|c| c.is_ascii_digit() || c == '.' || c == '_') {
+            Value::Str(raw.replace('_', ""))
+        } else {
+            return Err(err("unsupported value"));
+        };
+
+        tables.last_mut().unwrap().entries.push((key.to_owned(), value));
+    }
+
+    Ok(tables)
+}
+
+/// Removes a trailing `#` comment that is not inside a string.
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match quote {
+            Some('"') if escaped => escaped = false,
+            Some('"') if c == '\\' => escaped = true,
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if c == '#' => return &line[..i],
+            None => {}
+        }
+    }
+    line
+}
+
+fn unescape(s: &str) -> Option<String> {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next()? {
+            '"' => out.push('"'),
+            '\\' => out.push('\\'),
+            'n' => out.push('\n'),
+            't' => out.push('\t'),
+            _ => return None,
+        }
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_toml, Table};
+
+    fn keys(table: &Table) -> Vec<&str> {
+        table.entries.iter().map(|(key, _)| key.as_str()).collect()
+    }
+
+    #[test]
+    fn tables_and_values() {
+        let tables = parse_toml("verbose = true\n\
+                                 \n\
+                                 [profile.paranoid]\n\
+                                 method = \"gutmann\"\n\
+                                 passes = 1_000\n\
+                                 [ profile . quick ]\n\
+                                 no-zero = false\n")
+            .unwrap();
+        assert_eq!(tables.len(), 3);
+        assert_eq!(tables[0].name, "");
+        assert_eq!(tables[0].to_args(), ["--verbose"]);
+        assert_eq!(tables[1].name, "profile.paranoid");
+        assert_eq!(keys(&tables[1]), ["method", "passes"]);
+        assert_eq!(tables[1].to_args(), ["--method", "gutmann", "--iterations", "1000"]);
+        assert_eq!(tables[2].name, "profile.quick");
+        assert_eq!(tables[2].to_args(), ["--zero"]);
+    }
+
+    #[test]
+    fn false_turns_options_off() {
+        let tables = parse_toml("verbose = false\nverify = true\n").unwrap();
+        assert_eq!(tables[0].to_args(), ["--no-verbose", "--verify"]);
+    }
+
+    #[test]
+    fn quoting() {
+        let tables = parse_toml("a = \"x \\\"y\\\" \\\\ \\t\"\n\
+                                 b = 'c:\\dir'\n\
+                                 c = \"\"\n")
+            .unwrap();
+        assert_eq!(tables[0].to_args(),
+                   ["--a", "x \"y\" \\ \t", "--b", "c:\\dir", "--c", ""]);
+    }
+
+    #[test]
+    fn comments() {
+        let tables = parse_toml("# a comment\n\
+                                 report-dir = \"/var/log/#shrem\" # where\n\
+                                 b = 'x#y'#\n\
+                                 [profile.x] # a profile\n\
+                                 verify = true # yes\n")
+            .unwrap();
+        assert_eq!(tables[0].to_args(), ["--report-dir", "/var/log/#shrem", "--b", "x#y"]);
+        assert_eq!(tables[1].name, "profile.x");
+        assert_eq!(tables[1].to_args(), ["--verify"]);
+    }
+
+    #[test]
+    fn bad_input() {
+        for text in &["verbose", "= true", "a b = 1", "a = yes", "a = \"open", "a = 'open",
+                      "a = \"\\q\"", "[profile.x", "[[array]]", "[]", "[profile.]", "a = 1 2"] {
+            assert!(parse_toml(text).is_err(), "{}", text);
+        }
+        assert_eq!(parse_toml("a = 1\nb\n").err().unwrap(), "line 2: expected `key = value`");
+    }
+}
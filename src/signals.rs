@@ -0,0 +1,199 @@
+//! Signals: `SIGUSR1` (and `SIGINFO` where it exists) prints the status of the files being
+//! overwritten, like `dd` does; `SIGINT` and `SIGTERM` stop shredding.
+//!
+//! The signals are blocked in every thread and received by a dedicated thread with `sigwait`, so
+//! the handling code is not restricted to what is safe in a signal handler.
+
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use shrem::event::{format_bytes, Event};
+use shrem::{self, Config, ShremError};
+
+/// The signal that interrupted shredding, or 0.
+static SIGNAL: AtomicI32 = AtomicI32::new(0);
+
+/// Blocks the signals and starts the thread that receives them. Must be called before any other
+/// thread is started, so that they all inherit the signal mask.
+pub fn install(tracker: Arc<Tracker>) {
+    let set = unsafe {
+        let mut set = std::mem::zeroed();
+        libc::sigemptyset(&mut set);
+        libc::sigaddset(&mut set, libc::SIGINT);
+        libc::sigaddset(&mut set, libc::SIGTERM);
+        libc::sigaddset(&mut set, libc::SIGUSR1);
+        #[cfg(any(target_os = "macos", target_os = "ios", target_os = "freebsd",
+                  target_os = "openbsd", target_os = "netbsd", target_os = "dragonfly"))]
+        libc::sigaddset(&mut set, libc::SIGINFO);
+        libc::pthread_sigmask(libc::SIG_BLOCK, &set, std::ptr::null_mut());
+        set
+    };
+
+    thread::spawn(move || loop {
+        let mut sig = 0;
+        if unsafe { libc::sigwait(&set, &mut sig) } != 0 {
+            continue;
+        }
+
+        if sig == libc::SIGINT || sig == libc::SIGTERM {
+            if shrem::is_interrupted() {
+                std::process::exit(128 + sig);
+            }
+            SIGNAL.store(sig, Ordering::SeqCst);
+            shrem::interrupt();
+            eprintln!("shrem: interrupted, stopping (interrupt again to quit immediately)");
+        } else {
+            tracker.print_status();
+        }
+    });
+}
+
+/// The signal that interrupted shredding, if any.
+pub fn interrupted_by() -> Option<i32> {
+    match SIGNAL.load(Ordering::SeqCst) {
+        0 => None,
+        sig => Some(sig),
+    }
+}
+
+/// Follows the events to know which files are being overwritten and how far each has got.
+pub struct Tracker {
+    state: Mutex<State>,
+}
+
+struct State {
+    /// Files being overwritten, in the order they were started.
+    active: Vec<Active>,
+    /// Files that may have been written to, in the order they were started.
+    touched: Vec<PathBuf>,
+    destroyed: HashSet<PathBuf>,
+}
+
+struct Active {
+    path: PathBuf,
+    size: u64,
+    pass: usize,
+    passes: usize,
+    /// Bytes written in the current pass.
+    bytes: u64,
+}
+
+impl Tracker {
+    pub fn new() -> Tracker {
+        Tracker {
+            state: Mutex::new(State {
+                active: Vec::new(),
+                touched: Vec::new(),
+                destroyed: HashSet::new(),
+            }),
+        }
+    }
+
+    pub fn update(&self, event: &Event) {
+        let mut state = self.state.lock().unwrap();
+
+        match *event {
+            Event::Start { path, bytes } => {
+                state.touched.push(path.to_path_buf());
+                state.active.push(Active {
+                    path: path.to_path_buf(),
+                    size: bytes,
+                    pass: 0,
+                    passes: 0,
+                    bytes: 0,
+                });
+            }
+            Event::PassStart { path, pass, total, .. } => {
+                if let Some(active) = state.find(path) {
+                    active.pass = pass;
+                    active.passes = total;
+                    active.bytes = 0;
+                }
+            }
+            Event::Progress { path, bytes, .. } |
+            Event::PassDone { path, bytes, .. } => {
+                if let Some(active) = state.find(path) {
+                    active.bytes = bytes;
+                }
+            }
+            Event::Done { path, .. } => {
+                state.active.retain(|a| a.path != path);
+                state.destroyed.insert(path.to_path_buf());
+            }
+            Event::Error { path, error } => {
+                // A file that failed before its first pass has not been written to, unless it was
+                // a backend that was stopped: those do not report their passes.
+                let unwritten = state.find(path).is_some_and(|a| a.pass == 0) &&
+                                !matches!(*error, ShremError::Interrupted);
+                state.active.retain(|a| a.path != path);
+                if unwritten {
+                    state.touched.retain(|p| p != path);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn print_status(&self) {
+        let state = self.state.lock().unwrap();
+        for active in &state.active {
+            if active.pass == 0 {
+                eprintln!("shrem: {}: starting", active.path.display());
+            } else {
+                eprintln!("shrem: {}: pass {}/{}, {} of {}",
+                          active.path.display(),
+                          active.pass,
+                          active.passes,
+                          format_bytes(active.bytes),
+                          format_bytes(active.size));
+            }
+        }
+        eprintln!("shrem: {} files destroyed so far", state.destroyed.len());
+    }
+
+    /// Prints which files were destroyed, which were partially overwritten and which were not
+    /// touched at all. `paths` and `config` are those of the interrupted run.
+    pub fn print_outcome(&self, paths: &[&Path], config: &Config) {
+        let state = self.state.lock().unwrap();
+
+        let destroyed = state.touched
+            .iter()
+            .filter(|path| state.destroyed.contains(*path))
+            .collect::<Vec<_>>();
+        let partial = state.touched
+            .iter()
+            .filter(|path| !state.destroyed.contains(*path))
+            .collect::<Vec<_>>();
+
+        // Everything that is still there and was never written to.
+        let touched = state.touched.iter().collect::<HashSet<_>>();
+        let untouched = shrem::regular_files(paths, config)
+            .into_iter()
+            .map(|(path, _)| path)
+            .filter(|path| !touched.contains(path))
+            .collect::<Vec<_>>();
+
+        eprintln!("shrem: {} files destroyed, {} partially overwritten, {} untouched",
+                  destroyed.len(),
+                  partial.len(),
+                  untouched.len());
+        for path in &destroyed {
+            eprintln!("  destroyed: {}", path.display());
+        }
+        for path in &partial {
+            eprintln!("  partially overwritten: {}", path.display());
+        }
+        for path in &untouched {
+            eprintln!("  untouched: {}", path.display());
+        }
+    }
+}
+
+impl State {
+    fn find(&mut self, path: &Path) -> Option<&mut Active> {
+        self.active.iter_mut().find(|a| a.path == path)
+    }
+}